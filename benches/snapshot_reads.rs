@@ -0,0 +1,131 @@
+//! Reads of the database while its leases file is reloaded. Readers take the
+//! current snapshot from `db::DB` and list its devices or look one up, while
+//! a reload parses the file, records it in the store and installs the new
+//! snapshot.
+//!
+//! Run it with `cargo bench`.
+
+use std::{
+    hint::black_box,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use dhcpd_api::db::{self, Database, FileKind, SourceFile, DB};
+use tokio::runtime::Runtime;
+
+const LEASES: u32 = 2000;
+const READERS: usize = 8;
+const READS: usize = 200;
+const RELOADS: usize = 4;
+
+fn address(i: u32) -> Ipv4Addr {
+    Ipv4Addr::from(0x0a00_0000 + i)
+}
+
+/// A leases file of `LEASES` active leases, each client named with `name`.
+fn leases_file(name: &str) -> String {
+    (0..LEASES)
+        .map(|i| {
+            let [_, b, c, d] = i.to_be_bytes();
+            format!(
+                "lease {} {{
+  starts 1 2024/01/01 10:00:00;
+  ends 1 2099/01/01 10:00:00;
+  cltt 1 2024/01/01 10:00:00;
+  binding state active;
+  hardware ethernet 02:00:00:{b:02x}:{c:02x}:{d:02x};
+  client-hostname \"{name}-{i}\";
+}}
+",
+                address(i)
+            )
+        })
+        .collect()
+}
+
+/// Reloads `path` alternating between two versions of the file, so that
+/// every reload has something to install.
+struct Reloader {
+    file: SourceFile,
+    versions: [String; 2],
+    next: usize,
+}
+
+impl Reloader {
+    fn new(path: &Path) -> Self {
+        Self {
+            file: SourceFile {
+                source: "lan".to_owned(),
+                kind: FileKind::Leases,
+                path: path.to_owned(),
+            },
+            versions: [leases_file("host"), leases_file("renamed")],
+            next: 0,
+        }
+    }
+
+    async fn reload(&mut self, db: &DB) {
+        std::fs::write(&self.file.path, &self.versions[self.next % 2]).unwrap();
+        self.next += 1;
+        db::reload(db.clone(), &self.file).await.unwrap();
+    }
+}
+
+fn temp_path() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dhcpd-api-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join("dhcpd.leases")
+}
+
+/// Run `READERS` tasks of `READS` reads each alongside `RELOADS` reloads.
+async fn reads_during_reloads(db: &DB, reloader: &mut Reloader) {
+    let readers = (0..READERS)
+        .map(|reader| {
+            let db = db.clone();
+            tokio::spawn(async move {
+                for n in 0..READS {
+                    let i = u32::try_from(reader * READS + n).unwrap_or(0) % LEASES;
+                    let db = db.load();
+                    black_box(db.find_by_ip(address(i)));
+                    tokio::task::yield_now().await;
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for _ in 0..RELOADS {
+        reloader.reload(db).await;
+    }
+    for reader in readers {
+        reader.await.unwrap();
+    }
+}
+
+fn snapshot_reads(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let path = temp_path();
+    let mut reloader = Reloader::new(&path);
+    let db = DB::new(Database::in_memory(&["lan"]));
+    runtime.block_on(reloader.reload(&db));
+
+    let mut group = c.benchmark_group("snapshot");
+    group.sample_size(10);
+    group.bench_function("devices", |b| {
+        b.iter(|| black_box(db.load().devices().len()));
+    });
+    group.bench_function("reload", |b| {
+        b.iter(|| runtime.block_on(reloader.reload(&db)));
+    });
+    group.bench_function("reads_during_reloads", |b| {
+        b.iter(|| runtime.block_on(reads_during_reloads(&db, &mut reloader)));
+    });
+    group.finish();
+
+    if let Some(dir) = path.parent() {
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+criterion_group!(benches, snapshot_reads);
+criterion_main!(benches);
@@ -1,22 +1,116 @@
 use std::{net::SocketAddr, path::PathBuf};
 
+use chrono::{Duration, Utc};
+use chrono_tz::Tz;
 use clap::Parser;
 
+use crate::source::{self, SourceConfig, DEFAULT_SOURCE};
+
 #[derive(Debug, Parser)]
 #[command(author, version, about)]
 pub struct Args {
-    #[arg(long, default_value = "/var/dhcpd/var/db/dhcpd.leases")]
+    #[arg(
+        long,
+        default_value = "/var/dhcpd/var/db/dhcpd.leases",
+        conflicts_with = "sources"
+    )]
     pub dhcpd_leases: PathBuf,
 
-    #[arg(long, default_value = "/var/dhcpd/etc/dhcpd.conf")]
+    #[arg(
+        long,
+        default_value = "/var/dhcpd/etc/dhcpd.conf",
+        conflicts_with = "sources"
+    )]
     pub dhcpd_config: PathBuf,
 
+    /// A dhcpd instance, as `NAME=LEASES,CONFIG`. Repeat it for each
+    /// instance, instead of `--dhcpd-leases` and `--dhcpd-config`.
+    #[arg(long = "source", value_name = "NAME=LEASES,CONFIG")]
+    pub sources: Vec<SourceConfig>,
+
     #[arg(short, long, default_value = "0.0.0.0:16768")]
     pub listen: SocketAddr,
+
+    /// IANA zone (such as `Europe/Amsterdam`) to render timestamps in,
+    /// instead of UTC. Requests can override it with `?tz=`.
+    #[arg(long)]
+    pub timezone: Option<Tz>,
+
+    /// Database file for lease history and other state that outlives the
+    /// dhcpd files. Defaults to `dhcpd-api/state.sqlite` in the user's data
+    /// directory.
+    #[arg(long)]
+    pub state_db: Option<PathBuf>,
+
+    /// Days of lease history to keep, 0 keeps everything.
+    #[arg(long, default_value_t = 365, value_parser = parse_retention_days)]
+    pub history_retention_days: u32,
+
+    /// JSON file listing webhooks to send device changes to.
+    #[arg(long)]
+    pub webhooks: Option<PathBuf>,
+
+    /// A token clients must send as `Authorization: Bearer <TOKEN>` to
+    /// change annotations. Without one, nobody can unless
+    /// `--allow-loopback-writes` is given.
+    #[arg(long, value_name = "TOKEN")]
+    pub write_token: Option<String>,
+
+    /// Let clients on this machine change annotations without the token.
+    /// Don't use it behind a reverse proxy on the same machine: every
+    /// request it forwards comes from loopback.
+    #[arg(long)]
+    pub allow_loopback_writes: bool,
+
+    /// An origin, such as `https://dashboard.example.com`, whose pages may
+    /// open `/ws` besides pages served from this host. Repeat it for each.
+    #[arg(long = "ws-origin", value_name = "ORIGIN")]
+    pub ws_origins: Vec<String>,
 }
 
 impl Args {
     pub fn new() -> Self {
         Self::parse()
     }
+
+    /// The `--source` instances, or a single one named `default` for
+    /// `--dhcpd-leases` and `--dhcpd-config`.
+    pub fn sources(&self) -> Result<Vec<SourceConfig>, source::Error> {
+        if self.sources.is_empty() {
+            return Ok(vec![SourceConfig {
+                name: DEFAULT_SOURCE.to_owned(),
+                leases: self.dhcpd_leases.clone(),
+                config: self.dhcpd_config.clone(),
+            }]);
+        }
+        for (i, source) in self.sources.iter().enumerate() {
+            if self.sources[..i].iter().any(|s| s.name == source.name) {
+                return Err(source::Error::DuplicateSource(source.name.clone()));
+            }
+        }
+
+        Ok(self.sources.clone())
+    }
+
+    pub fn state_db(&self) -> Option<PathBuf> {
+        self.state_db
+            .clone()
+            .or_else(|| dirs::data_dir().map(|dir| dir.join("dhcpd-api").join("state.sqlite")))
+    }
+
+    pub fn history_retention(&self) -> Option<Duration> {
+        (self.history_retention_days > 0)
+            .then(|| Duration::try_days(i64::from(self.history_retention_days)))
+            .flatten()
+    }
+}
+
+/// Days that fit in a [`Duration`] and can be taken from the current time,
+/// so pruning history can't overflow.
+fn parse_retention_days(value: &str) -> Result<u32, String> {
+    let days = value.parse::<u32>().map_err(|e| e.to_string())?;
+    Duration::try_days(i64::from(days))
+        .and_then(|retention| Utc::now().checked_sub_signed(retention))
+        .map(|_| days)
+        .ok_or_else(|| format!("{days} days reaches past the earliest time"))
 }
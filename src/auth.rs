@@ -0,0 +1,113 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts},
+};
+use sha2::{Digest, Sha256};
+
+/// Who may change data: holders of the `--write-token` and, with
+/// `--allow-loopback-writes`, clients on this machine.
+#[derive(Debug, Clone, Default)]
+pub struct WriteAccess {
+    pub token: Option<Arc<str>>,
+    pub loopback: bool,
+}
+
+impl WriteAccess {
+    /// Compares digests so the time taken doesn't tell how much of `given`
+    /// was right.
+    fn matches(&self, given: &str) -> bool {
+        self.token
+            .as_deref()
+            .is_some_and(|token| Sha256::digest(token) == Sha256::digest(given))
+    }
+}
+
+/// Permission to change data: the `--write-token` sent as a bearer token or,
+/// with `--allow-loopback-writes`, a client on this machine. Without either,
+/// nobody can write.
+#[derive(Debug)]
+pub struct CanWrite;
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CanWrite
+where
+    S: Send + Sync,
+{
+    type Rejection = crate::Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let access = parts
+            .extensions
+            .get::<WriteAccess>()
+            .cloned()
+            .unwrap_or_default();
+        let bearer = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| access.matches(given));
+        let loopback = access.loopback
+            && parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .is_some_and(|ConnectInfo(addr)| addr.ip().is_loopback());
+
+        if bearer || loopback {
+            Ok(Self)
+        } else {
+            Err(crate::Error::Unauthorized)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use axum::http::Request;
+
+    use super::*;
+
+    async fn can_write(
+        token: Option<&str>,
+        loopback: bool,
+        from: &str,
+        bearer: Option<&str>,
+    ) -> bool {
+        let access = WriteAccess {
+            token: token.map(Arc::from),
+            loopback,
+        };
+        let mut request = Request::builder()
+            .extension(access)
+            .extension(ConnectInfo(from.parse::<SocketAddr>().unwrap()));
+        if let Some(bearer) = bearer {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {bearer}"));
+        }
+        let (mut parts, ()) = request.body(()).unwrap().into_parts();
+        CanWrite::from_request_parts(&mut parts, &()).await.is_ok()
+    }
+
+    #[tokio::test]
+    async fn test_can_write() {
+        // Without a token or loopback writes, nobody, not even this machine
+        // (a reverse proxy on it would let anyone through).
+        assert!(!can_write(None, false, "127.0.0.1:5000", None).await);
+        assert!(!can_write(None, false, "10.0.0.5:5000", Some("guess")).await);
+
+        // With loopback writes, only from this machine.
+        assert!(can_write(None, true, "127.0.0.1:5000", None).await);
+        assert!(can_write(None, true, "[::1]:5000", None).await);
+        assert!(!can_write(None, true, "10.0.0.5:5000", None).await);
+
+        // With a token, from anywhere as long as it is sent.
+        let token = Some("s3cret");
+        assert!(can_write(token, false, "10.0.0.5:5000", Some("s3cret")).await);
+        assert!(!can_write(token, false, "10.0.0.5:5000", Some("s3cre")).await);
+        assert!(!can_write(token, false, "127.0.0.1:5000", None).await);
+        assert!(can_write(token, true, "127.0.0.1:5000", None).await);
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use axum::{
+    async_trait,
+    body::{Body, Bytes},
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use flate2::{write::GzEncoder, Compression};
+use tokio::sync::OnceCell;
+
+/// Stop caching new query strings past this many, so odd queries can't use
+/// up memory.
+const MAX_ENTRIES: usize = 256;
+
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+
+/// Serialized response bodies for one database snapshot, keyed by path and
+/// query string. Each snapshot starts with an empty cache, so nothing here
+/// outlives the data it was made from.
+///
+/// Device listings also depend on the time: `remaining_seconds` and
+/// `since_last_transaction_seconds` count whole seconds from the database's
+/// clock, and which leases are active or `expiring_within` follows from
+/// them. So a body is only reused within the clock second it was made in.
+/// That still lets a burst of clients polling the same listing share one
+/// serialization and one compression of it, and never shows a count more
+/// than a second old.
+#[derive(Debug, Default)]
+pub struct ResponseCache {
+    entries: Mutex<HashMap<String, Arc<CachedBody>>>,
+}
+
+impl ResponseCache {
+    /// The cached body for `key` made at `now`, or a new one from `make`.
+    pub fn get_or_insert<E>(
+        &self,
+        key: String,
+        now: DateTime<Utc>,
+        make: impl FnOnce() -> Result<Vec<u8>, E>,
+    ) -> Result<Arc<CachedBody>, E> {
+        let second = now.timestamp();
+        let fresh = |body: &&Arc<CachedBody>| body.second == second;
+        if let Some(body) = self.entries().get(&key).filter(fresh) {
+            return Ok(body.clone());
+        }
+
+        // Two requests may both make the body; either result is fine.
+        let body = Arc::new(CachedBody::new(make()?, second));
+        let mut entries = self.entries();
+        if entries.len() < MAX_ENTRIES || entries.contains_key(&key) {
+            entries.insert(key, body.clone());
+        }
+        Ok(body)
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, Arc<CachedBody>>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A JSON body, with its compressed variants made on first use.
+#[derive(Debug)]
+pub struct CachedBody {
+    /// The clock second, as a Unix timestamp, the body was made in.
+    second: i64,
+    json: Bytes,
+    gzip: OnceCell<Option<Bytes>>,
+    brotli: OnceCell<Option<Bytes>>,
+}
+
+impl CachedBody {
+    fn new(json: Vec<u8>, second: i64) -> Self {
+        Self {
+            second,
+            json: json.into(),
+            gzip: OnceCell::new(),
+            brotli: OnceCell::new(),
+        }
+    }
+
+    /// The body in `encoding`, or plain JSON if compressing failed. Large
+    /// listings take a while to compress, so that runs on the blocking pool.
+    pub async fn encoded(&self, encoding: Encoding) -> (Bytes, Encoding) {
+        let compressed = match encoding {
+            Encoding::Identity => None,
+            Encoding::Gzip => compress(&self.gzip, &self.json, gzip).await,
+            Encoding::Brotli => compress(&self.brotli, &self.json, brotli).await,
+        };
+        match compressed {
+            Some(body) => (body, encoding),
+            None => (self.json.clone(), Encoding::Identity),
+        }
+    }
+}
+
+async fn compress(
+    cell: &OnceCell<Option<Bytes>>,
+    json: &Bytes,
+    f: fn(&[u8]) -> Option<Bytes>,
+) -> Option<Bytes> {
+    let json = json.clone();
+    let made = cell.get_or_init(|| async move {
+        let compressed = tokio::task::spawn_blocking(move || f(&json)).await;
+        compressed.ok().flatten()
+    });
+    made.await.clone()
+}
+
+fn gzip(data: &[u8]) -> Option<Bytes> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).ok()?;
+    Some(encoder.finish().ok()?.into())
+}
+
+fn brotli(data: &[u8]) -> Option<Bytes> {
+    let mut encoder =
+        brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+    encoder.write_all(data).ok()?;
+    encoder.flush().ok()?;
+    Some(encoder.into_inner().into())
+}
+
+/// A content coding the server can send.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Identity,
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    /// The best coding `accept`, an `Accept-Encoding` value, allows.
+    pub fn negotiate(accept: &str) -> Self {
+        let mut gzip = false;
+        let mut brotli = false;
+        for item in accept.split(',') {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let refused = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.0)
+            });
+            if refused {
+                continue;
+            }
+            match name.as_str() {
+                "br" => brotli = true,
+                "gzip" | "x-gzip" => gzip = true,
+                "*" => (brotli, gzip) = (true, true),
+                _ => (),
+            }
+        }
+
+        if brotli {
+            Self::Brotli
+        } else if gzip {
+            Self::Gzip
+        } else {
+            Self::Identity
+        }
+    }
+
+    fn header(self) -> Option<&'static str> {
+        match self {
+            Self::Identity => None,
+            Self::Gzip => Some("gzip"),
+            Self::Brotli => Some("br"),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Encoding
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let accept = parts
+            .headers
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        Ok(Self::negotiate(accept))
+    }
+}
+
+/// A cached JSON body, in the coding the client asked for.
+pub struct CachedResponse {
+    body: Bytes,
+    encoding: Encoding,
+}
+
+impl CachedResponse {
+    pub async fn new(body: &CachedBody, encoding: Encoding) -> Self {
+        let (body, encoding) = body.encoded(encoding).await;
+        Self { body, encoding }
+    }
+}
+
+impl IntoResponse for CachedResponse {
+    fn into_response(self) -> Response {
+        let (body, encoding) = (self.body, self.encoding);
+        let mut response = Response::new(Body::from(body));
+        let headers = response.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+        if let Some(coding) = encoding.header() {
+            headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(coding));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(Encoding::negotiate(""), Encoding::Identity);
+        assert_eq!(Encoding::negotiate("gzip, deflate"), Encoding::Gzip);
+        assert_eq!(Encoding::negotiate("gzip, deflate, br"), Encoding::Brotli);
+        assert_eq!(Encoding::negotiate("br;q=0, gzip;q=0.5"), Encoding::Gzip);
+        assert_eq!(Encoding::negotiate("*"), Encoding::Brotli);
+        assert_eq!(Encoding::negotiate("identity"), Encoding::Identity);
+    }
+
+    #[tokio::test]
+    async fn test_cached_variants() {
+        let cache = ResponseCache::default();
+        let json = br#"{"devices":[]}"#.repeat(100);
+        let now = Utc::now();
+        let body = cache
+            .get_or_insert("/".to_owned(), now, || Ok::<_, ()>(json.clone()))
+            .unwrap();
+        let again = cache
+            .get_or_insert("/".to_owned(), now, || Err(()))
+            .expect("cached");
+        assert!(Arc::ptr_eq(&body, &again));
+
+        // A second later the counts in it are out of date.
+        let later = now + chrono::Duration::seconds(1);
+        let remade = cache.get_or_insert("/".to_owned(), later, || Ok::<_, ()>(json.clone()));
+        assert!(!Arc::ptr_eq(&body, &remade.unwrap()));
+
+        let (gzipped, encoding) = body.encoded(Encoding::Gzip).await;
+        assert_eq!(encoding, Encoding::Gzip);
+        let mut plain = Vec::new();
+        flate2::read::GzDecoder::new(&gzipped[..])
+            .read_to_end(&mut plain)
+            .unwrap();
+        assert_eq!(plain, json);
+
+        let (brotlied, encoding) = body.encoded(Encoding::Brotli).await;
+        assert_eq!(encoding, Encoding::Brotli);
+        assert!(brotlied.len() < json.len());
+        let mut plain = Vec::new();
+        brotli::Decompressor::new(&brotlied[..], 4096)
+            .read_to_end(&mut plain)
+            .unwrap();
+        assert_eq!(plain, json);
+    }
+}
@@ -0,0 +1,739 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, BTreeSet, VecDeque},
+    fmt,
+    hash::{BuildHasher, Hasher},
+    net::Ipv4Addr,
+    num::ParseIntError,
+    str::FromStr,
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cidr::{InvalidCidr, Ipv4Cidr},
+    db::FileKind,
+    model::{Device, Host, Lease, LeaseTime, MacAddr},
+    render::{Render, Time, TimeFormat},
+    source::{self, SourceFilter, SourceNames},
+    vendor_macs::VendorMapping,
+};
+
+/// How many changes are kept. Pollers that fall further behind than this
+/// have to fetch `/` again.
+const JOURNAL_CAPACITY: usize = 10_000;
+
+/// Something that changed about a device between two reloads.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Change {
+    /// Increases by one for every change, starting at 1. Restarts with the
+    /// process, which gets a new [`Journal::epoch`].
+    pub seq: u64,
+    pub observed_at: Time,
+    pub source: String,
+    pub hardware_ethernet: MacAddr,
+    #[serde(flatten)]
+    pub kind: ChangeKind,
+}
+
+impl Render for Change {
+    fn render(&mut self, format: TimeFormat) {
+        self.observed_at.render(format);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ChangeKind {
+    /// The MAC has an active lease and didn't before.
+    DeviceAdded {
+        address: Ipv4Addr,
+        hostname: Option<String>,
+    },
+    /// The MAC's lease expired or was released.
+    DeviceLeft {
+        address: Ipv4Addr,
+        hostname: Option<String>,
+    },
+    IpChanged {
+        from: Ipv4Addr,
+        to: Ipv4Addr,
+    },
+    HostnameChanged {
+        /// The device's current address.
+        address: Ipv4Addr,
+        from: Option<String>,
+        to: Option<String>,
+    },
+    StaticAdded {
+        label: String,
+        to: Mapping,
+    },
+    StaticRemoved {
+        label: String,
+        from: Mapping,
+    },
+    StaticChanged {
+        label: String,
+        from: Mapping,
+        to: Mapping,
+    },
+}
+
+impl Change {
+    /// The addresses the change is about.
+    pub fn addresses(&self) -> Vec<Ipv4Addr> {
+        match &self.kind {
+            ChangeKind::DeviceAdded { address, .. }
+            | ChangeKind::DeviceLeft { address, .. }
+            | ChangeKind::HostnameChanged { address, .. } => vec![*address],
+            ChangeKind::IpChanged { from, to } => vec![*from, *to],
+            ChangeKind::StaticAdded { to: mapping, .. }
+            | ChangeKind::StaticRemoved { from: mapping, .. } => vec![mapping.address],
+            ChangeKind::StaticChanged { from, to, .. } => vec![from.address, to.address],
+        }
+    }
+}
+
+impl ChangeKind {
+    pub fn action(&self) -> Action {
+        match self {
+            Self::DeviceAdded { .. } | Self::StaticAdded { .. } => Action::Added,
+            Self::DeviceLeft { .. } | Self::StaticRemoved { .. } => Action::Removed,
+            Self::IpChanged { .. } | Self::HostnameChanged { .. } | Self::StaticChanged { .. } => {
+                Action::Changed
+            }
+        }
+    }
+}
+
+/// Whether a change added, changed or removed a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Added,
+    Changed,
+    Removed,
+}
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Added => "added",
+            Self::Changed => "changed",
+            Self::Removed => "removed",
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    InvalidCidr(#[from] InvalidCidr),
+
+    #[error(transparent)]
+    InvalidSource(#[from] source::Error),
+}
+
+/// A [`ChangeFilter`] as given in JSON, by WebSocket subscribers and in the
+/// webhooks file. Empty fields match everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    pub macs: Vec<MacAddr>,
+    pub cidr: Option<String>,
+    pub vendor: Option<String>,
+    pub events: Vec<Action>,
+    pub source: Option<String>,
+}
+
+impl FilterConfig {
+    pub fn build(self, names: &SourceNames) -> Result<ChangeFilter, Error> {
+        let subnet = match self.cidr.filter(|cidr| !cidr.is_empty()) {
+            Some(cidr) => Some(cidr.parse()?),
+            None => None,
+        };
+
+        Ok(ChangeFilter {
+            source: SourceFilter::new(self.source, names)?,
+            subnet,
+            vendor: self.vendor.filter(|vendor| !vendor.is_empty()),
+            macs: self.macs,
+            actions: self.events,
+        })
+    }
+}
+
+/// Which changes a subscriber wants. Empty fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct ChangeFilter {
+    pub source: SourceFilter,
+    /// Changes involving an address in this subnet.
+    pub subnet: Option<Ipv4Cidr>,
+    /// Changes to devices whose MAC belongs to this vendor.
+    pub vendor: Option<String>,
+    pub macs: Vec<MacAddr>,
+    pub actions: Vec<Action>,
+}
+
+impl ChangeFilter {
+    pub fn matches(&self, change: &Change, vendors: &VendorMapping) -> bool {
+        if !self.source.matches(&change.source) {
+            return false;
+        }
+        if let Some(subnet) = self.subnet {
+            if !change.addresses().into_iter().any(|ip| subnet.contains(ip)) {
+                return false;
+            }
+        }
+        if let Some(vendor) = &self.vendor {
+            if vendors.get_vendor_name(&change.hardware_ethernet) != Some(vendor.as_str()) {
+                return false;
+            }
+        }
+
+        (self.macs.is_empty() || self.macs.contains(&change.hardware_ethernet))
+            && (self.actions.is_empty() || self.actions.contains(&change.kind.action()))
+    }
+
+    /// Whether `device` is one this filter's changes could be about.
+    pub fn matches_device(&self, device: &Device) -> bool {
+        if let Some(subnet) = self.subnet {
+            if !subnet.contains(device.address()) {
+                return false;
+            }
+        }
+
+        self.source.matches(device.source())
+            && (self.vendor.is_none() || device.vendor() == self.vendor.as_deref())
+            && (self.macs.is_empty() || self.macs.contains(device.hardware_ethernet()))
+    }
+}
+
+/// A static mapping, as compared between reloads.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Mapping {
+    pub address: Ipv4Addr,
+    pub hardware_ethernet: MacAddr,
+    pub hostname: Option<String>,
+}
+
+impl From<&Host> for Mapping {
+    fn from(host: &Host) -> Self {
+        Self {
+            address: host.fixed_address,
+            hardware_ethernet: host.hardware_ethernet.clone(),
+            hostname: host.hostname.clone(),
+        }
+    }
+}
+
+/// Where a client is up to in the journal. Sequence numbers restart with the
+/// process, and the epoch says which process they came from; a cursor
+/// without one is taken to be from this process. Written `<epoch>-<seq>`,
+/// or just `<seq>`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cursor {
+    pub epoch: Option<String>,
+    pub seq: u64,
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.epoch {
+            Some(epoch) => write!(f, "{epoch}-{}", self.seq),
+            None => write!(f, "{}", self.seq),
+        }
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (epoch, seq) = match s.rsplit_once('-') {
+            Some((epoch, seq)) => (Some(epoch.to_owned()), seq),
+            None => (None, s),
+        };
+        Ok(Self {
+            epoch,
+            seq: seq.parse()?,
+        })
+    }
+}
+
+/// A random id for a journal, so that each process has a different one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Epoch(String);
+
+impl Default for Epoch {
+    fn default() -> Self {
+        let id = RandomState::new().build_hasher().finish();
+        Self(format!("{id:016x}"))
+    }
+}
+
+/// The active lease of a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Active {
+    address: Ipv4Addr,
+    hostname: Option<String>,
+    starts: LeaseTime,
+    ends: LeaseTime,
+}
+
+impl Active {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.ends.is_some_and(|ends| ends < now)
+    }
+}
+
+/// The recent changes, and what they were computed against.
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    changes: VecDeque<Arc<Change>>,
+    epoch: Epoch,
+    last_seq: u64,
+    /// Active leases by source and MAC as of the last leases reload.
+    active: BTreeMap<(String, MacAddr), Active>,
+    /// Static mappings by source and label as of the last hosts reload.
+    mappings: BTreeMap<(String, String), Mapping>,
+    /// Files loaded at least once. The first load of a file isn't a change.
+    loaded: BTreeSet<(String, FileKind)>,
+}
+
+impl Journal {
+    /// Record what changed in `files`, each a source and which of its files
+    /// was loaded. Leases that expired since the last reload are noticed here
+    /// too, whichever source they are in.
+    pub fn record(
+        &mut self,
+        files: &[(String, FileKind)],
+        leases: &[Lease],
+        hosts: &[Host],
+        now: DateTime<Utc>,
+    ) {
+        let mut changes = Vec::new();
+        if files.iter().any(|(_, kind)| *kind == FileKind::Leases) {
+            let active = active_leases(leases, now);
+            let diff = diff_leases(&self.active, &active);
+            changes.extend(diff.into_iter().map(|change| (FileKind::Leases, change)));
+            self.active = active;
+        }
+        if files.iter().any(|(_, kind)| *kind == FileKind::Hosts) {
+            let mappings = hosts
+                .iter()
+                .map(|host| ((host.source.clone(), host.label.clone()), host.into()))
+                .collect();
+            let diff = diff_mappings(&self.mappings, &mappings);
+            changes.extend(diff.into_iter().map(|change| (FileKind::Hosts, change)));
+            self.mappings = mappings;
+        }
+
+        let first_loads = files
+            .iter()
+            .filter(|file| self.loaded.insert((*file).clone()))
+            .collect::<Vec<_>>();
+        let changes = changes.into_iter().filter_map(|(file_kind, change)| {
+            let first = first_loads
+                .iter()
+                .any(|(source, first)| *source == change.0 && *first == file_kind);
+            (!first).then_some(change)
+        });
+        self.push(changes, now);
+    }
+
+    /// Whether a lease active as of the last reload has expired by `now`.
+    pub fn has_expired(&self, now: DateTime<Utc>) -> bool {
+        self.active.values().any(|lease| lease.is_expired(now))
+    }
+
+    /// Record the leases that expired by `now` without their file changing.
+    pub fn expire(&mut self, now: DateTime<Utc>) {
+        let active = self
+            .active
+            .iter()
+            .filter(|(_, lease)| !lease.is_expired(now))
+            .map(|(key, lease)| (key.clone(), lease.clone()))
+            .collect();
+        let diff = diff_leases(&self.active, &active);
+        self.active = active;
+        self.push(diff, now);
+    }
+
+    fn push(&mut self, changes: impl IntoIterator<Item = DiffEntry>, now: DateTime<Utc>) {
+        for (source, hardware_ethernet, kind) in changes {
+            self.last_seq += 1;
+            self.changes.push_back(Arc::new(Change {
+                seq: self.last_seq,
+                observed_at: now.into(),
+                source,
+                hardware_ethernet,
+                kind,
+            }));
+        }
+        while self.changes.len() > JOURNAL_CAPACITY {
+            self.changes.pop_front();
+        }
+    }
+
+    /// The sequence number of the newest change, or 0 if there are none.
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    pub fn epoch(&self) -> &str {
+        &self.epoch.0
+    }
+
+    /// The cursor for `seq` in this journal.
+    pub fn cursor(&self, seq: u64) -> Cursor {
+        Cursor {
+            epoch: Some(self.epoch.0.clone()),
+            seq,
+        }
+    }
+
+    fn is_current(&self, cursor: &Cursor) -> bool {
+        cursor.epoch.as_deref().unwrap_or(self.epoch()) == self.epoch()
+    }
+
+    /// Whether changes after `since` are missing, because they were dropped
+    /// or `since` came from before a restart.
+    pub fn missed(&self, since: &Cursor) -> bool {
+        let first = self.changes.front().map_or(self.last_seq + 1, |c| c.seq);
+        !self.is_current(since) || since.seq > self.last_seq || since.seq + 1 < first
+    }
+
+    /// The changes after `since`, oldest first. None if `since` is from
+    /// another process.
+    pub fn since(&self, since: &Cursor) -> impl Iterator<Item = &Change> {
+        let start = if self.is_current(since) {
+            self.changes
+                .partition_point(|change| change.seq <= since.seq)
+        } else {
+            self.changes.len()
+        };
+        self.changes.range(start..).map(Arc::as_ref)
+    }
+}
+
+type DiffEntry = (String, MacAddr, ChangeKind);
+type Diff = Vec<DiffEntry>;
+
+/// The newest unexpired lease of each device.
+fn active_leases(leases: &[Lease], now: DateTime<Utc>) -> BTreeMap<(String, MacAddr), Active> {
+    let mut active = BTreeMap::new();
+    for lease in leases.iter().filter(|lease| !lease.is_expired(now)) {
+        let key = (lease.source.clone(), lease.hardware_ethernet.clone());
+        let entry = Active {
+            address: lease.address,
+            hostname: lease.client_hostname.clone(),
+            starts: lease.starts,
+            ends: lease.ends,
+        };
+        active
+            .entry(key)
+            .and_modify(|current: &mut Active| {
+                if entry.starts >= current.starts {
+                    *current = entry.clone();
+                }
+            })
+            .or_insert(entry);
+    }
+    active
+}
+
+fn diff_leases(
+    old: &BTreeMap<(String, MacAddr), Active>,
+    new: &BTreeMap<(String, MacAddr), Active>,
+) -> Diff {
+    let mut diff = Vec::new();
+    for (key, lease) in new {
+        let mut push = |kind| diff.push((key.0.clone(), key.1.clone(), kind));
+        let Some(before) = old.get(key) else {
+            push(ChangeKind::DeviceAdded {
+                address: lease.address,
+                hostname: lease.hostname.clone(),
+            });
+            continue;
+        };
+        if before.address != lease.address {
+            push(ChangeKind::IpChanged {
+                from: before.address,
+                to: lease.address,
+            });
+        }
+        if before.hostname != lease.hostname {
+            push(ChangeKind::HostnameChanged {
+                address: lease.address,
+                from: before.hostname.clone(),
+                to: lease.hostname.clone(),
+            });
+        }
+    }
+    for (key, lease) in old.iter().filter(|(key, _)| !new.contains_key(*key)) {
+        let kind = ChangeKind::DeviceLeft {
+            address: lease.address,
+            hostname: lease.hostname.clone(),
+        };
+        diff.push((key.0.clone(), key.1.clone(), kind));
+    }
+    diff
+}
+
+fn diff_mappings(
+    old: &BTreeMap<(String, String), Mapping>,
+    new: &BTreeMap<(String, String), Mapping>,
+) -> Diff {
+    let mut diff = Vec::new();
+    for (key, mapping) in new {
+        let (source, label) = key;
+        let kind = match old.get(key) {
+            None => ChangeKind::StaticAdded {
+                label: label.clone(),
+                to: mapping.clone(),
+            },
+            Some(before) if before != mapping => ChangeKind::StaticChanged {
+                label: label.clone(),
+                from: before.clone(),
+                to: mapping.clone(),
+            },
+            Some(_) => continue,
+        };
+        diff.push((source.clone(), mapping.hardware_ethernet.clone(), kind));
+    }
+    for ((source, label), mapping) in old.iter().filter(|(key, _)| !new.contains_key(*key)) {
+        let kind = ChangeKind::StaticRemoved {
+            label: label.clone(),
+            from: mapping.clone(),
+        };
+        diff.push((source.clone(), mapping.hardware_ethernet.clone(), kind));
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+
+    fn lease(d: u8, address: u8, hostname: &str, ends: DateTime<Utc>) -> Lease {
+        Lease::test(d)
+            .with_address([10, 0, 0, address])
+            .with_hostname(hostname)
+            .with_times(ends - Duration::hours(1), ends)
+    }
+
+    fn host(label: &str, address: u8) -> Host {
+        Host {
+            label: label.to_owned(),
+            fixed_address: Ipv4Addr::new(10, 0, 1, address),
+            hardware_ethernet: MacAddr::from([0, 0, 0, 0, 1, address]),
+            hostname: None,
+            source: "lan".to_owned(),
+        }
+    }
+
+    fn lan(kind: FileKind) -> Vec<(String, FileKind)> {
+        vec![("lan".to_owned(), kind)]
+    }
+
+    fn at(seq: u64) -> Cursor {
+        Cursor { epoch: None, seq }
+    }
+
+    fn events(journal: &Journal, since: u64) -> Vec<(u64, &ChangeKind)> {
+        journal
+            .since(&at(since))
+            .map(|c| (c.seq, &c.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_record_leases() {
+        let t0 = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let later = t0 + Duration::hours(2);
+        let mut journal = Journal::default();
+
+        // The first load is the starting point, not a change.
+        let first = vec![
+            lease(1, 1, "one", later),
+            lease(2, 2, "two", t0 + Duration::minutes(30)),
+        ];
+        journal.record(&lan(FileKind::Leases), &first, &[], t0);
+        assert_eq!(journal.last_seq(), 0);
+        assert!(!journal.missed(&at(0)));
+
+        // .1 moves and is renamed, .3 arrives; at t1, .2 has expired.
+        let t1 = t0 + Duration::hours(1);
+        let second = vec![
+            lease(1, 9, "uno", later),
+            lease(2, 2, "two", t0),
+            lease(3, 3, "three", later),
+        ];
+        journal.record(&lan(FileKind::Leases), &second, &[], t1);
+        assert_eq!(
+            events(&journal, 0),
+            [
+                (
+                    1,
+                    &ChangeKind::IpChanged {
+                        from: Ipv4Addr::new(10, 0, 0, 1),
+                        to: Ipv4Addr::new(10, 0, 0, 9),
+                    }
+                ),
+                (
+                    2,
+                    &ChangeKind::HostnameChanged {
+                        address: Ipv4Addr::new(10, 0, 0, 9),
+                        from: Some("one".to_owned()),
+                        to: Some("uno".to_owned()),
+                    }
+                ),
+                (
+                    3,
+                    &ChangeKind::DeviceAdded {
+                        address: Ipv4Addr::new(10, 0, 0, 3),
+                        hostname: Some("three".to_owned()),
+                    }
+                ),
+                (
+                    4,
+                    &ChangeKind::DeviceLeft {
+                        address: Ipv4Addr::new(10, 0, 0, 2),
+                        hostname: Some("two".to_owned()),
+                    }
+                ),
+            ]
+        );
+        assert_eq!(events(&journal, 3).len(), 1);
+        assert!(events(&journal, 4).is_empty());
+        assert!(!journal.missed(&at(4)));
+        assert!(journal.missed(&at(5)));
+
+        // From before a restart, even though the number is in range.
+        let cursor = journal.cursor(3);
+        assert_eq!(cursor.to_string().parse::<Cursor>().unwrap(), cursor);
+        assert_eq!(journal.since(&cursor).count(), 1);
+        let restarted = Journal::default();
+        assert_ne!(restarted.epoch(), journal.epoch());
+        assert!(journal.missed(&restarted.cursor(3)));
+        assert_eq!(journal.since(&restarted.cursor(3)).count(), 0);
+    }
+
+    #[test]
+    fn test_expire() {
+        let t0 = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let mut journal = Journal::default();
+        let leases = vec![
+            lease(1, 1, "one", t0 + Duration::hours(2)),
+            lease(2, 2, "two", t0 + Duration::minutes(30)),
+        ];
+        journal.record(&lan(FileKind::Leases), &leases, &[], t0);
+        assert!(!journal.has_expired(t0));
+
+        let t1 = t0 + Duration::hours(1);
+        assert!(journal.has_expired(t1));
+        journal.expire(t1);
+        assert!(!journal.has_expired(t1));
+        assert_eq!(
+            events(&journal, 0),
+            [(
+                1,
+                &ChangeKind::DeviceLeft {
+                    address: Ipv4Addr::new(10, 0, 0, 2),
+                    hostname: Some("two".to_owned()),
+                }
+            )]
+        );
+
+        // The next reload doesn't see it leave again.
+        journal.record(&lan(FileKind::Leases), &leases, &[], t1);
+        assert_eq!(journal.last_seq(), 1);
+    }
+
+    #[test]
+    fn test_record_hosts() {
+        let now = Utc::now();
+        let mut journal = Journal::default();
+        journal.record(
+            &lan(FileKind::Hosts),
+            &[],
+            &[host("a", 1), host("b", 2)],
+            now,
+        );
+        assert_eq!(journal.last_seq(), 0);
+
+        let mut moved = host("b", 2);
+        moved.fixed_address = Ipv4Addr::new(10, 0, 1, 20);
+        journal.record(
+            &lan(FileKind::Hosts),
+            &[],
+            &[moved.clone(), host("c", 3)],
+            now,
+        );
+        let kinds = journal.since(&at(0)).map(|c| &c.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                &ChangeKind::StaticChanged {
+                    label: "b".to_owned(),
+                    from: (&host("b", 2)).into(),
+                    to: (&moved).into(),
+                },
+                &ChangeKind::StaticAdded {
+                    label: "c".to_owned(),
+                    to: (&host("c", 3)).into(),
+                },
+                &ChangeKind::StaticRemoved {
+                    label: "a".to_owned(),
+                    from: (&host("a", 1)).into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_change_filter() {
+        let vendors = VendorMapping::parse("").unwrap();
+        let change = Change {
+            seq: 1,
+            observed_at: Time::default(),
+            source: "lan".to_owned(),
+            hardware_ethernet: MacAddr::from([0, 0, 0, 0, 0, 1]),
+            kind: ChangeKind::IpChanged {
+                from: Ipv4Addr::new(10, 0, 0, 1),
+                to: Ipv4Addr::new(10, 0, 1, 1),
+            },
+        };
+        assert_eq!(change.kind.action(), Action::Changed);
+
+        let subnet = |s: &str| ChangeFilter {
+            subnet: Some(s.parse().unwrap()),
+            ..ChangeFilter::default()
+        };
+        assert!(ChangeFilter::default().matches(&change, &vendors));
+        assert!(subnet("10.0.1.0/24").matches(&change, &vendors));
+        assert!(!subnet("10.0.2.0/24").matches(&change, &vendors));
+
+        let macs = |d: u8| ChangeFilter {
+            macs: vec![
+                MacAddr::from([0, 0, 0, 0, 0, 9]),
+                MacAddr::from([0, 0, 0, 0, 0, d]),
+            ],
+            ..ChangeFilter::default()
+        };
+        assert!(macs(1).matches(&change, &vendors));
+        assert!(!macs(2).matches(&change, &vendors));
+
+        let vendor = ChangeFilter {
+            vendor: Some("Acme".to_owned()),
+            ..ChangeFilter::default()
+        };
+        assert!(!vendor.matches(&change, &vendors));
+    }
+}
@@ -0,0 +1,181 @@
+use std::{fmt, net::Ipv4Addr, num::ParseIntError, str::FromStr};
+
+use serde::Serialize;
+
+#[derive(Debug, thiserror::Error)]
+pub enum InvalidCidr {
+    #[error("cidr missing prefix length")]
+    MissingPrefix,
+
+    #[error("cidr address parse error: {0}")]
+    Address(#[from] std::net::AddrParseError),
+
+    #[error("cidr prefix parse error: {0}")]
+    Prefix(#[from] ParseIntError),
+
+    #[error("cidr prefix length must be at most 32")]
+    PrefixTooLong,
+}
+
+/// An IPv4 network such as `10.0.20.0/24`. Host bits are masked off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Cidr {
+    network: Ipv4Addr,
+    prefix: u8,
+}
+
+impl Ipv4Cidr {
+    pub fn new(address: Ipv4Addr, prefix: u8) -> Result<Self, InvalidCidr> {
+        if prefix > 32 {
+            return Err(InvalidCidr::PrefixTooLong);
+        }
+        let network = Ipv4Addr::from(u32::from(address) & Self::mask(prefix));
+
+        Ok(Self { network, prefix })
+    }
+
+    fn mask(prefix: u8) -> u32 {
+        u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0)
+    }
+
+    pub fn first(self) -> Ipv4Addr {
+        self.network
+    }
+
+    pub fn last(self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.network) | !Self::mask(self.prefix))
+    }
+
+    pub fn contains(self, ip: Ipv4Addr) -> bool {
+        u32::from(ip) & Self::mask(self.prefix) == u32::from(self.network)
+    }
+}
+
+impl FromStr for Ipv4Cidr {
+    type Err = InvalidCidr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = s.split_once('/').ok_or(InvalidCidr::MissingPrefix)?;
+        Self::new(address.parse()?, prefix.parse()?)
+    }
+}
+
+impl fmt::Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// A run of consecutive addresses that have no lease or static mapping.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Gap {
+    pub start: Ipv4Addr,
+    pub end: Ipv4Addr,
+    pub size: u64,
+}
+
+impl Gap {
+    fn new(start: u32, end: u32) -> Self {
+        Self {
+            start: Ipv4Addr::from(start),
+            end: Ipv4Addr::from(end),
+            size: u64::from(end - start) + 1,
+        }
+    }
+}
+
+/// Find the unused runs between `start` and `end` (inclusive), given the
+/// used addresses in ascending order.
+pub fn gaps<I>(start: Ipv4Addr, end: Ipv4Addr, used: I) -> Vec<Gap>
+where
+    I: IntoIterator<Item = Ipv4Addr>,
+{
+    let (start, end) = (u32::from(start), u32::from(end));
+    let mut gaps = Vec::new();
+    // `None` once the whole range up to `end` is accounted for.
+    let mut next = Some(start);
+
+    for ip in used.into_iter().map(u32::from) {
+        let Some(expected) = next else {
+            break;
+        };
+        if ip < expected || ip > end {
+            continue;
+        }
+        if ip > expected {
+            gaps.push(Gap::new(expected, ip - 1));
+        }
+        next = ip.checked_add(1).filter(|&n| n <= end);
+    }
+    if let Some(expected) = next {
+        gaps.push(Gap::new(expected, end));
+    }
+
+    gaps
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use super::*;
+
+    #[test]
+    fn test_parse_cidr() {
+        let cidr = Ipv4Cidr::from_str("10.0.20.17/24").unwrap();
+        assert_eq!(cidr.first(), Ipv4Addr::new(10, 0, 20, 0));
+        assert_eq!(cidr.last(), Ipv4Addr::new(10, 0, 20, 255));
+        assert_eq!(cidr.to_string(), "10.0.20.0/24");
+        assert!(cidr.contains(Ipv4Addr::new(10, 0, 20, 99)));
+        assert!(!cidr.contains(Ipv4Addr::new(10, 0, 21, 1)));
+    }
+
+    #[test]
+    fn test_cidr_edges() {
+        let all = Ipv4Cidr::from_str("1.2.3.4/0").unwrap();
+        assert_eq!(all.first(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(all.last(), Ipv4Addr::BROADCAST);
+
+        let one = Ipv4Cidr::from_str("1.2.3.4/32").unwrap();
+        assert_eq!(one.first(), one.last());
+
+        Ipv4Cidr::from_str("1.2.3.4/33").expect_err("Prefix too long");
+        Ipv4Cidr::from_str("1.2.3.4").expect_err("Missing prefix");
+        Ipv4Cidr::from_str("1.2.3/8").expect_err("Bad address");
+    }
+
+    #[test]
+    fn test_gaps() {
+        let ip = |d| Ipv4Addr::new(10, 0, 0, d);
+        let gaps = gaps(ip(0), ip(9), [ip(0), ip(3), ip(3), ip(4), ip(8)]);
+        assert_eq!(
+            gaps,
+            vec![
+                Gap {
+                    start: ip(1),
+                    end: ip(2),
+                    size: 2
+                },
+                Gap {
+                    start: ip(5),
+                    end: ip(7),
+                    size: 3
+                },
+                Gap {
+                    start: ip(9),
+                    end: ip(9),
+                    size: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gaps_full_range() {
+        let gaps = gaps(Ipv4Addr::UNSPECIFIED, Ipv4Addr::BROADCAST, []);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].size, 1 << 32);
+
+        let end = Ipv4Addr::BROADCAST;
+        assert!(super::gaps(end, end, [end]).is_empty());
+    }
+}
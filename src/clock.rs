@@ -0,0 +1,30 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+
+/// Where "now" comes from. Everything that depends on the current time takes
+/// it from the database's clock so that it can be pinned in tests.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that is stuck at one instant.
+#[cfg(test)]
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
@@ -1,31 +1,160 @@
 use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    net::Ipv4Addr,
     path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
 };
 
+use arc_swap::ArcSwap;
 use chrono::{DateTime, Utc};
-use tokio::sync::Mutex;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::AsyncReadExt,
+    sync::{watch, Mutex},
+};
 use tokio_util::sync::CancellationToken;
 
 use crate::{
+    cache::ResponseCache,
+    changes::Journal,
+    clock::{Clock, SystemClock},
     dhcp_parsers::{self, hosts, leases},
-    model::{Host, Lease},
+    identity::Identities,
+    index::{Entries, Index},
+    macaddr::MacPrefix,
+    model::{Annotation, Device, Host, Lease, MacAddr, MergedDevice, Sighting},
+    render::{Render, Time, TimeFormat},
+    search::Matcher,
+    source::{SourceConfig, SourceFilter},
+    store::Store,
     vendor_macs::VendorMapping,
+    watch::{FileChange, FileStamp, FileWatcher, WatchMode},
 };
 
-pub type DB = Arc<Mutex<Database>>;
+/// The current [`Database`], as an immutable snapshot. Readers never wait
+/// for writers: a writer changes a copy of the snapshot and swaps it in.
+#[derive(Clone)]
+pub struct DB {
+    current: Arc<ArcSwap<Database>>,
+    /// Writers take turns so that none of their changes are lost.
+    writer: Arc<Mutex<()>>,
+    /// The last sequence number in the current snapshot's journal.
+    journaled: Arc<watch::Sender<u64>>,
+}
+
+impl DB {
+    pub fn new(db: Database) -> Self {
+        let (journaled, _) = watch::channel(db.journal.last_seq());
+        Self {
+            current: Arc::new(ArcSwap::from_pointee(db)),
+            writer: Arc::new(Mutex::new(())),
+            journaled: Arc::new(journaled),
+        }
+    }
+
+    /// The current snapshot. It doesn't change while it is held.
+    pub fn load(&self) -> Arc<Database> {
+        self.current.load_full()
+    }
+
+    /// Notified each time a snapshot with new changes in its journal is
+    /// installed.
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.journaled.subscribe()
+    }
+
+    /// Change a copy of the current snapshot with `f` and install it, with
+    /// a new generation and an empty response cache. Cloning a [`Database`]
+    /// only clones handles to its tables, so `f` should be quick; do
+    /// expensive work before calling this.
+    pub async fn update<R>(&self, f: impl FnOnce(&mut Database) -> R) -> R {
+        let _writer = self.writer.lock().await;
+        self.install(f)
+    }
+
+    /// Change a copy of the current snapshot's status, such as a file's
+    /// [`ReloadStatus`], with `f` and install it. The data is unchanged, so
+    /// it keeps the generation and the cached responses; those only show
+    /// the status as of the second they were made.
+    pub async fn update_status<R>(&self, f: impl FnOnce(&mut Database) -> R) -> R {
+        let _writer = self.writer.lock().await;
+        let mut next = Database::clone(&self.current.load());
+        let result = f(&mut next);
+        self.current.store(Arc::new(next));
+        result
+    }
+
+    /// Install the annotations the store has now. They are read while
+    /// holding the writer lock, so a slower request can't install an older
+    /// set over a newer one.
+    pub async fn reload_annotations(&self) -> Result<(), crate::store::Error> {
+        let _writer = self.writer.lock().await;
+        let annotations = self.load().store.annotations().await?;
+        self.install(|db| db.annotations = Arc::new(annotations));
+        Ok(())
+    }
+
+    /// Install a copy of the current snapshot changed by `f`. The caller
+    /// holds the writer lock.
+    fn install<R>(&self, f: impl FnOnce(&mut Database) -> R) -> R {
+        let mut next = Database::clone(&self.current.load());
+        next.generation += 1;
+        next.responses = Arc::default();
+        let result = f(&mut next);
+        let last_seq = next.journal.last_seq();
+        self.current.store(Arc::new(next));
+        self.journaled.send_if_modified(|journaled| {
+            let moved = *journaled != last_seq;
+            *journaled = last_seq;
+            moved
+        });
+        result
+    }
+}
+
+/// How often to check mtimes when there are no file events.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often to check mtimes as well as watching for events.
+const EVENTS_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How many times to read a file that fails to parse.
+const RELOAD_ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry, doubled after each one.
+const RELOAD_BACKOFF: Duration = Duration::from_millis(100);
 
 #[derive(Clone)]
 pub struct Database {
-    pub leases: Vec<Lease>,
-    pub hosts: Vec<Host>,
+    pub clock: Arc<dyn Clock>,
+    pub store: Store,
 
-    pub vendor_mapping: VendorMapping,
+    pub leases: Arc<Vec<Lease>>,
+    pub hosts: Arc<Vec<Host>>,
 
-    pub last_update_leases: Option<DateTime<Utc>>,
-    pub last_update_hosts: Option<DateTime<Utc>>,
+    pub vendor_mapping: Arc<VendorMapping>,
+    pub index: Arc<Index>,
+    pub identities: Arc<Identities>,
+    /// First and last sightings of every MAC, from the store.
+    pub sightings: Arc<BTreeMap<MacAddr, Sighting>>,
+    /// Annotations by MAC, kept in step with the store.
+    pub annotations: Arc<BTreeMap<MacAddr, Annotation>>,
+
+    /// The configured dhcpd instances, in command line order.
+    pub sources: Vec<SourceStatus>,
     pub last_update_check: Option<DateTime<Utc>>,
+    pub watch_mode: WatchMode,
+
+    /// Recent changes to devices, for `/changes`.
+    pub journal: Arc<Journal>,
+
+    /// Counts the snapshots installed so far.
+    pub generation: u64,
+    /// Response bodies made from this snapshot.
+    pub responses: Arc<ResponseCache>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -41,46 +170,444 @@ pub enum Error {
 
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Store(#[from] crate::store::Error),
+
+    #[error("unknown source: {0}")]
+    UnknownSource(String),
+
+    #[error("reload task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+impl Error {
+    /// Parse errors may come from reading a file dhcpd is still writing.
+    fn is_parse(&self) -> bool {
+        matches!(self, Error::HostsParser(_) | Error::LeasesParser(_))
+    }
+}
+
+/// Which of a source's files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FileKind {
+    Leases,
+    Hosts,
+}
+
+/// One file to load into the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    pub source: String,
+    pub kind: FileKind,
+    pub path: PathBuf,
+}
+
+/// A configured dhcpd instance and how reloading its files has gone.
+#[derive(Debug, Clone, Default)]
+pub struct SourceStatus {
+    pub name: String,
+    pub leases: ReloadStatus,
+    pub hosts: ReloadStatus,
+}
+
+/// How reloading one of the dhcpd files has gone.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReloadStatus {
+    pub last_attempt: Time,
+    pub last_success: Time,
+    pub last_error: Option<String>,
+    /// Failed reloads since the last success. A reload that needed retries
+    /// but succeeded in the end doesn't count.
+    pub consecutive_failures: u32,
+    /// The file currently loaded.
+    pub file: Option<FileStamp>,
+    /// SHA-256 of the contents currently loaded.
+    pub checksum: Option<String>,
+}
+
+impl Render for ReloadStatus {
+    fn render(&mut self, format: TimeFormat) {
+        self.last_attempt.render(format);
+        self.last_success.render(format);
+        self.file.render(format);
+    }
 }
 
 impl Database {
-    pub async fn new() -> Result<Self, Error> {
-        let leases = Vec::new();
-        let hosts = Vec::new();
+    pub async fn new(store: Store, sources: &[SourceConfig]) -> Result<Self, Error> {
         let vendor_mapping = VendorMapping::fetch(true).await?;
-        let last_update_leases = None;
-        let last_update_hosts = None;
         let last_update_check = None;
+        let annotations = store.annotations().await?;
+        let sightings = store.sightings().await?;
         let db = Database {
-            leases,
-            hosts,
-            vendor_mapping,
-            last_update_leases,
-            last_update_hosts,
+            clock: Arc::new(SystemClock),
+            store,
+            leases: Arc::default(),
+            hosts: Arc::default(),
+            vendor_mapping: Arc::new(vendor_mapping),
+            index: Arc::default(),
+            identities: Arc::default(),
+            sightings: Arc::new(sightings),
+            annotations: Arc::new(annotations),
+            sources: sources
+                .iter()
+                .map(|source| SourceStatus {
+                    name: source.name.clone(),
+                    ..SourceStatus::default()
+                })
+                .collect(),
             last_update_check,
+            watch_mode: WatchMode::default(),
+            journal: Arc::default(),
+            generation: 0,
+            responses: Arc::default(),
         };
 
         Ok(db)
     }
+
+    /// An empty database with an in-memory store and no vendors.
+    #[allow(clippy::unwrap_used)]
+    pub fn in_memory(sources: &[&str]) -> Self {
+        Database {
+            clock: Arc::new(SystemClock),
+            store: Store::open_in_memory(None).unwrap(),
+            leases: Arc::default(),
+            hosts: Arc::default(),
+            vendor_mapping: Arc::new(VendorMapping::parse("").unwrap()),
+            index: Arc::default(),
+            identities: Arc::default(),
+            sightings: Arc::default(),
+            annotations: Arc::default(),
+            sources: sources
+                .iter()
+                .map(|name| SourceStatus {
+                    name: (*name).to_owned(),
+                    ..SourceStatus::default()
+                })
+                .collect(),
+            last_update_check: None,
+            watch_mode: WatchMode::default(),
+            journal: Arc::default(),
+            generation: 0,
+            responses: Arc::default(),
+        }
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    pub fn reload_status(&self, file: &SourceFile) -> Option<&ReloadStatus> {
+        let source = self.sources.iter().find(|s| s.name == file.source)?;
+        Some(match file.kind {
+            FileKind::Leases => &source.leases,
+            FileKind::Hosts => &source.hosts,
+        })
+    }
+
+    fn reload_status_mut(&mut self, file: &SourceFile) -> Option<&mut ReloadStatus> {
+        let source = self.sources.iter_mut().find(|s| s.name == file.source)?;
+        Some(match file.kind {
+            FileKind::Leases => &mut source.leases,
+            FileKind::Hosts => &mut source.hosts,
+        })
+    }
+
+    /// The most recent successful reload of `kind` in the sources `filter`
+    /// allows.
+    pub fn last_success(&self, kind: FileKind, filter: &SourceFilter) -> Option<DateTime<Utc>> {
+        self.sources
+            .iter()
+            .filter(|source| filter.matches(&source.name))
+            .filter_map(|source| match kind {
+                FileKind::Leases => source.leases.last_success.at(),
+                FileKind::Hosts => source.hosts.last_success.at(),
+            })
+            .max()
+    }
+
+    /// Where `source` is in the command line order, for keeping the leases
+    /// and hosts of each source together.
+    fn source_order(&self, source: &str) -> usize {
+        self.sources
+            .iter()
+            .position(|s| s.name == source)
+            .unwrap_or(usize::MAX)
+    }
+
+    pub fn lease_device(&self, i: usize, now: DateTime<Utc>) -> Option<Device<'_>> {
+        let lease = self.leases.get(i)?;
+        let device = Device::from_lease(lease, self.index.lease_vendor(i), now);
+        Some(self.decorate(device))
+    }
+
+    pub fn host_device(&self, i: usize) -> Option<Device<'_>> {
+        let host = self.hosts.get(i)?;
+        Some(self.decorate(Device::from_host(host, self.index.host_vendor(i))))
+    }
+
+    /// Attach what the store knows about the device's MAC.
+    fn decorate<'a>(&'a self, device: Device<'a>) -> Device<'a> {
+        let mac = device.hardware_ethernet();
+        device
+            .with_sighting(self.sightings.get(mac))
+            .with_annotation(self.annotations.get(mac))
+    }
+
+    /// Every lease followed by every static mapping, in file order.
+    pub fn devices(&self) -> Vec<Device<'_>> {
+        let now = self.now();
+        let leases = (0..self.leases.len()).filter_map(|i| self.lease_device(i, now));
+        let hosts = (0..self.hosts.len()).filter_map(|i| self.host_device(i));
+        leases.chain(hosts).collect()
+    }
+
+    pub fn devices_for(&self, entries: Option<&Entries>) -> Vec<Device<'_>> {
+        let Some(entries) = entries else {
+            return Vec::new();
+        };
+        let now = self.now();
+        let leases = entries
+            .leases
+            .iter()
+            .filter_map(|&i| self.lease_device(i, now));
+        let hosts = entries.hosts.iter().filter_map(|&i| self.host_device(i));
+        leases.chain(hosts).collect()
+    }
+
+    /// Devices for the leases that were active at `at`, from the current
+    /// leases and from `history`, as if `at` was now. Static mappings aren't
+    /// included since their history isn't recorded.
+    pub fn devices_at<'a>(&'a self, history: &'a [Lease], at: DateTime<Utc>) -> Vec<Device<'a>> {
+        let active = |lease: &Lease| lease.starts.is_some_and(|s| s <= at) && !lease.is_expired(at);
+        let mut seen = BTreeSet::new();
+        let current = self
+            .leases
+            .iter()
+            .enumerate()
+            .filter(|(_, lease)| active(lease))
+            .map(|(i, lease)| (lease, self.index.lease_vendor(i)));
+        let recorded = history.iter().filter(|lease| active(lease)).map(|lease| {
+            let vendor = self
+                .vendor_mapping
+                .get_vendor_name(&lease.hardware_ethernet);
+            (lease, vendor)
+        });
+
+        current
+            .chain(recorded)
+            .filter(|(lease, _)| {
+                seen.insert((lease.address, &lease.hardware_ethernet, lease.starts))
+            })
+            .map(|(lease, vendor)| self.decorate(Device::from_lease(lease, vendor, at)))
+            .collect()
+    }
+
+    pub fn find_by_ip(&self, ip: Ipv4Addr) -> Vec<Device<'_>> {
+        self.devices_for(self.index.by_ip(ip))
+    }
+
+    pub fn find_by_mac(&self, mac: &MacAddr) -> Vec<Device<'_>> {
+        self.devices_for(self.index.by_mac(mac))
+    }
+
+    pub fn find_by_mac_prefix(&self, prefix: &MacPrefix) -> Vec<Device<'_>> {
+        self.index
+            .by_mac_prefix(prefix)
+            .into_iter()
+            .flat_map(|entries| self.devices_for(Some(entries)))
+            .collect()
+    }
+
+    /// Devices with an address between `start` and `end` inclusive, sorted
+    /// by address.
+    pub fn find_in_range(&self, start: Ipv4Addr, end: Ipv4Addr) -> Vec<Device<'_>> {
+        self.index
+            .ip_range(start..=end)
+            .flat_map(|(_, entries)| self.devices_for(Some(entries)))
+            .collect()
+    }
+
+    pub fn merged_device(&self, mac: &MacAddr, filter: &SourceFilter) -> Option<MergedDevice<'_>> {
+        let (mac, entries) = self.index.by_mac_entry(mac)?;
+        self.merge(mac, entries, filter)
+    }
+
+    /// One merged record per MAC address, sorted by MAC.
+    pub fn merged_devices(&self, filter: &SourceFilter) -> Vec<MergedDevice<'_>> {
+        self.index
+            .macs()
+            .filter_map(|(mac, entries)| self.merge(mac, entries, filter))
+            .collect()
+    }
+
+    /// Merge the leases and hosts `filter` allows, or `None` if it allows
+    /// none of them.
+    fn merge<'a>(
+        &'a self,
+        mac: &'a MacAddr,
+        entries: &Entries,
+        filter: &SourceFilter,
+    ) -> Option<MergedDevice<'a>> {
+        let leases = entries
+            .leases
+            .iter()
+            .copied()
+            .filter(|&i| filter.matches(&self.leases[i].source))
+            .collect::<Vec<_>>();
+        let hosts = entries
+            .hosts
+            .iter()
+            .copied()
+            .filter(|&i| filter.matches(&self.hosts[i].source))
+            .collect::<Vec<_>>();
+        if leases.is_empty() && hosts.is_empty() {
+            return None;
+        }
+        let vendor = leases
+            .first()
+            .and_then(|&i| self.index.lease_vendor(i))
+            .or_else(|| hosts.first().and_then(|&i| self.index.host_vendor(i)));
+        let leases = leases.iter().map(|&i| &self.leases[i]).collect();
+        let hosts = hosts.iter().map(|&i| &self.hosts[i]).collect::<Vec<_>>();
+
+        Some(
+            MergedDevice::new(mac, leases, &hosts, vendor, self.now())
+                .with_annotation(self.annotations.get(mac)),
+        )
+    }
+
+    /// Whether any lease or static mapping `filter` allows has `mac`.
+    pub fn has_mac(&self, mac: &MacAddr, filter: &SourceFilter) -> bool {
+        self.index.by_mac(mac).is_some_and(|entries| {
+            entries
+                .leases
+                .iter()
+                .any(|&i| filter.matches(&self.leases[i].source))
+                || entries
+                    .hosts
+                    .iter()
+                    .any(|&i| filter.matches(&self.hosts[i].source))
+        })
+    }
+
+    /// MACs first seen at or after `since`, oldest first, with their vendor.
+    /// With a source filter, only MACs currently in one of its files.
+    pub fn new_devices(
+        &self,
+        since: DateTime<Utc>,
+        filter: &SourceFilter,
+    ) -> Vec<(&MacAddr, Option<&str>, &Sighting)> {
+        let mut found = self
+            .sightings
+            .iter()
+            .filter(|(_, sighting)| sighting.first_seen.at().is_some_and(|first| first >= since))
+            .filter(|(mac, _)| filter.is_all() || self.has_mac(mac, filter))
+            .map(|(mac, sighting)| (mac, self.vendor_mapping.get_vendor_name(mac), sighting))
+            .collect::<Vec<_>>();
+        found.sort_by_key(|(mac, _, sighting)| (sighting.first_seen.at(), *mac));
+        found
+    }
+
+    /// Devices whose hostname, host label or annotation matches, best match
+    /// first.
+    pub fn search(&self, matcher: &Matcher) -> Vec<Device<'_>> {
+        let mut scored = self
+            .index
+            .names()
+            .filter_map(|(name, entries)| Some((matcher.score(name)?, entries)))
+            .collect::<Vec<_>>();
+        scored.extend(self.annotations.iter().filter_map(|(mac, annotation)| {
+            let score = annotation
+                .texts()
+                .filter_map(|text| matcher.score(&text.to_lowercase()))
+                .reduce(f64::max)?;
+            Some((score, self.index.by_mac(mac)?))
+        }));
+        scored.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+
+        let now = self.now();
+        let mut seen_leases = BTreeSet::new();
+        let mut seen_hosts = BTreeSet::new();
+        let mut devices = Vec::new();
+        for (_, entries) in scored {
+            for &i in &entries.leases {
+                if seen_leases.insert(i) {
+                    devices.extend(self.lease_device(i, now));
+                }
+            }
+            for &i in &entries.hosts {
+                if seen_hosts.insert(i) {
+                    devices.extend(self.host_device(i));
+                }
+            }
+        }
+
+        devices
+    }
 }
 
 pub async fn watch_files(
     db: DB,
     shutdown: CancellationToken,
-    dhcpd_config: &PathBuf,
-    dhcpd_leases: &PathBuf,
+    sources: &[SourceConfig],
 ) -> Result<(), Error> {
-    let dhcpd_config = std::fs::canonicalize(dhcpd_config)?;
-    let dhcpd_leases = std::fs::canonicalize(dhcpd_leases)?;
+    let mut files = Vec::new();
+    for source in sources {
+        files.push(SourceFile {
+            source: source.name.clone(),
+            kind: FileKind::Leases,
+            path: std::fs::canonicalize(&source.leases)?,
+        });
+        files.push(SourceFile {
+            source: source.name.clone(),
+            kind: FileKind::Hosts,
+            path: std::fs::canonicalize(&source.config)?,
+        });
+    }
 
-    update_leases(db.clone(), &dhcpd_leases).await?;
-    update_hosts(db.clone(), &dhcpd_config).await?;
+    load(&db, &files).await?;
 
-    // check for changes every 60 seconds, using just mtime stat calls.
-    let mut interval = tokio::time::interval(Duration::from_secs(10));
+    let paths = files
+        .iter()
+        .map(|file| file.path.as_path())
+        .collect::<Vec<_>>();
+    let mut watcher = match FileWatcher::new(&paths) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            tracing::warn!("File events unavailable, polling instead: {}", e);
+            None
+        }
+    };
+    // Where events can miss changes they only make reloads sooner, and the
+    // files are still polled as often as without them.
+    let (mode, poll) = if watcher.is_some() && FileWatcher::reliable() {
+        (WatchMode::Events, EVENTS_POLL_INTERVAL)
+    } else {
+        (WatchMode::Polling, POLL_INTERVAL)
+    };
+    db.update_status(|db| db.watch_mode = mode).await;
+
+    // mtime checks catch anything the events missed, or everything when
+    // there are no events.
+    let mut interval = tokio::time::interval(poll);
     loop {
         tokio::select! {
-            _ = interval.tick() => check_files(db.clone(), &dhcpd_config, &dhcpd_leases).await,
+            _ = interval.tick() => check_files(db.clone(), &files).await,
+            changed = next_change(&mut watcher) => {
+                let Some(changed) = changed else {
+                    tracing::warn!("File events stopped, polling instead");
+                    watcher = None;
+                    db.update_status(|db| db.watch_mode = WatchMode::Polling).await;
+                    interval = tokio::time::interval(POLL_INTERVAL);
+                    continue;
+                };
+                for file in files.iter().filter(|file| changed.contains(&file.path)) {
+                    reload_logged(db.clone(), file).await;
+                }
+            }
             () = shutdown.cancelled() => break,
         }
     }
@@ -88,75 +615,641 @@ pub async fn watch_files(
     Ok(())
 }
 
-async fn check_files(db: DB, dhcpd_config: &PathBuf, dhcpd_leases: &PathBuf) {
-    let (last_update_leases, last_update_hosts) = {
-        let mut db = db.lock().await;
-        db.last_update_check.replace(Utc::now());
-        (db.last_update_leases, db.last_update_hosts)
+/// Load every file. They are all read before any is recorded, so that the
+/// store and the journal see every source at once rather than the sources not
+/// read yet disappearing.
+async fn load(db: &DB, files: &[SourceFile]) -> Result<(), Error> {
+    let mut read = Vec::new();
+    for file in files {
+        if let Some(contents) = read_file(db, file).await? {
+            read.push((file.source.as_str(), contents));
+        }
+    }
+    update(db, read).await
+}
+
+/// The next batch of changed files, or never when there is no watcher.
+async fn next_change(watcher: &mut Option<FileWatcher>) -> Option<BTreeSet<PathBuf>> {
+    match watcher {
+        Some(watcher) => watcher.changed().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn check_files(db: DB, files: &[SourceFile]) {
+    let loaded = {
+        db.update_status(|db| db.last_update_check = Some(db.now()))
+            .await;
+        let db = db.load();
+        files
+            .iter()
+            .map(|file| {
+                db.reload_status(file)
+                    .and_then(|status| status.file.clone())
+            })
+            .collect::<Vec<_>>()
     };
 
-    if file_changed(last_update_leases, dhcpd_leases).await {
-        update_leases(db.clone(), dhcpd_leases)
-            .await
-            .unwrap_or_else(|e| tracing::error!("Failed to update leases: {}", e));
+    for (file, loaded) in files.iter().zip(loaded) {
+        if file_changed(loaded.as_ref(), &file.path).await {
+            reload_logged(db.clone(), file).await;
+        }
     }
-    if file_changed(last_update_hosts, dhcpd_config).await {
-        update_hosts(db.clone(), dhcpd_config)
-            .await
-            .unwrap_or_else(|e| tracing::error!("Failed to update hosts: {}", e));
+
+    // dhcpd only rewrites its leases file when something happens, so leases
+    // that run out are noticed here rather than at the next reload.
+    let now = db.load().now();
+    if db.load().journal.has_expired(now) {
+        db.update(|db| Arc::make_mut(&mut db.journal).expire(now))
+            .await;
     }
 }
 
-async fn file_changed(last_update: Option<DateTime<Utc>>, file: &PathBuf) -> bool {
-    let Some(last_update) = last_update else {
+async fn reload_logged(db: DB, file: &SourceFile) {
+    let what = match file.kind {
+        FileKind::Leases => "leases",
+        FileKind::Hosts => "hosts",
+    };
+    reload(db, file)
+        .await
+        .unwrap_or_else(|e| tracing::error!("Failed to update {} {}: {}", file.source, what, e));
+}
+
+/// Whether `file` is no longer the one stamped `loaded`.
+async fn file_changed(loaded: Option<&FileStamp>, file: &Path) -> bool {
+    let Some(loaded) = loaded else {
         return true;
     };
 
-    let metadata = match tokio::fs::metadata(file).await {
-        Ok(metadata) => metadata,
+    match FileStamp::of(file).await {
+        Ok(stamp) => stamp.change_from(loaded) != FileChange::Unchanged,
         Err(e) => {
             tracing::error!("Failed to stat file: {}", e);
-            return false;
+            false
+        }
+    }
+}
+
+/// A file's parsed contents.
+enum Contents {
+    Leases(Vec<Lease>),
+    Hosts(Vec<Host>),
+}
+
+/// Reload `file`, keeping what was loaded before if it fails.
+pub async fn reload(db: DB, file: &SourceFile) -> Result<(), Error> {
+    match read_file(&db, file).await? {
+        Some(contents) => update(&db, vec![(file.source.as_str(), contents)]).await,
+        None => Ok(()),
+    }
+}
+
+/// Read and parse `file`, recording how it went in its [`ReloadStatus`].
+/// `None` if its contents are what is already loaded. Parse errors are
+/// retried with backoff, as they are usually a torn read of a file dhcpd is
+/// writing.
+async fn read_file(db: &DB, file: &SourceFile) -> Result<Option<Contents>, Error> {
+    let mut backoff = RELOAD_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let result = read_once(db, file).await;
+        let last_attempt = match &result {
+            Ok(_) => true,
+            Err(e) => !e.is_parse() || attempt == RELOAD_ATTEMPTS,
+        };
+        let recorded = db
+            .update_status(|db| {
+                let now = db.now();
+                let status = db.reload_status_mut(file)?;
+                status.last_attempt = now.into();
+                match &result {
+                    Ok((stamp, checksum, _)) => {
+                        status.last_success = now.into();
+                        status.last_error = None;
+                        status.consecutive_failures = 0;
+                        status.file = Some(stamp.clone());
+                        status.checksum = Some(checksum.clone());
+                    }
+                    Err(e) => {
+                        status.last_error = Some(e.to_string());
+                        if last_attempt {
+                            status.consecutive_failures += 1;
+                        }
+                    }
+                }
+                Some(())
+            })
+            .await;
+        if recorded.is_none() {
+            return Err(Error::UnknownSource(file.source.clone()));
+        }
+        match result {
+            Ok((_, _, contents)) => return Ok(contents),
+            Err(e) if last_attempt => return Err(e),
+            Err(e) => tracing::warn!(
+                "Failed to parse {}, retrying in {:?}: {}",
+                file.path.display(),
+                backoff,
+                e
+            ),
         }
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+/// Read and parse `path`, returning its stamp and checksum. Contents that
+/// match what is already loaded are not parsed again.
+async fn read_once(
+    db: &DB,
+    file: &SourceFile,
+) -> Result<(FileStamp, String, Option<Contents>), Error> {
+    let path = &file.path;
+    // Stamp the open file rather than the path, in case it is replaced
+    // while being read.
+    let mut handle = tokio::fs::File::open(path).await?;
+    let stamp = FileStamp::new(&handle.metadata().await?);
+    let mut buf = String::new();
+    handle.read_to_string(&mut buf).await?;
+    let checksum = format!("{:x}", Sha256::digest(buf.as_bytes()));
+
+    let (change, unchanged) = {
+        let db = db.load();
+        let status = db.reload_status(file);
+        (
+            status
+                .and_then(|status| status.file.as_ref())
+                .map(|loaded| stamp.change_from(loaded)),
+            status.and_then(|status| status.checksum.as_ref()) == Some(&checksum),
+        )
     };
+    match change {
+        Some(FileChange::Replaced) => tracing::info!("{} was replaced", path.display()),
+        Some(FileChange::Truncated) => tracing::warn!("{} was truncated", path.display()),
+        _ => (),
+    }
+    if unchanged {
+        return Ok((stamp, checksum, None));
+    }
 
-    let modified: DateTime<Utc> = match metadata.modified() {
-        Ok(modified) => modified.into(),
-        Err(e) => {
-            tracing::error!("Failed to get modified time: {}", e);
-            return false;
+    let source = file.source.clone();
+    let contents = match file.kind {
+        FileKind::Leases => {
+            tokio::task::spawn_blocking(move || {
+                let mut new_leases = leases::parse(&buf)?;
+                for lease in &mut new_leases {
+                    lease.source.clone_from(&source);
+                }
+                Ok::<_, Error>(Contents::Leases(new_leases))
+            })
+            .await??
+        }
+        FileKind::Hosts => {
+            tokio::task::spawn_blocking(move || {
+                let mut new_hosts = hosts::parse(&buf)?;
+                for host in &mut new_hosts {
+                    host.source.clone_from(&source);
+                }
+                Ok::<_, Error>(Contents::Hosts(new_hosts))
+            })
+            .await??
         }
     };
 
-    modified > last_update
+    Ok((stamp, checksum, Some(contents)))
+}
+
+/// Replace the leases from `source` with `new_leases`.
+pub async fn update_leases(db: &DB, source: &str, new_leases: Vec<Lease>) -> Result<(), Error> {
+    update(db, vec![(source, Contents::Leases(new_leases))]).await
 }
 
-pub async fn update_leases<P>(db: DB, dhcpd_leases: P) -> Result<(), Error>
-where
-    P: AsRef<Path>,
-{
-    let dhcpd_leases = dhcpd_leases.as_ref();
-    let buf = tokio::fs::read_to_string(dhcpd_leases).await?;
-    let new_leases = leases::parse(&buf)?;
-    {
-        let mut db = db.lock().await;
-        db.leases = new_leases;
-        db.last_update_leases.replace(Utc::now());
+/// Replace what each source in `files` had loaded from that kind of file
+/// with the new contents, as one change.
+async fn update(db: &DB, files: Vec<(&str, Contents)>) -> Result<(), Error> {
+    if files.is_empty() {
+        return Ok(());
     }
+    let snapshot = db.load();
+    let mut loaded = Vec::new();
+    let mut new_leases: Option<Vec<Lease>> = None;
+    let mut new_hosts: Option<Vec<Host>> = None;
+    for (source, contents) in files {
+        match contents {
+            Contents::Leases(leases) => {
+                let all = new_leases.get_or_insert_with(|| snapshot.leases.to_vec());
+                all.retain(|lease| lease.source != source);
+                all.extend(leases);
+                loaded.push((source.to_owned(), FileKind::Leases));
+            }
+            Contents::Hosts(hosts) => {
+                let all = new_hosts.get_or_insert_with(|| snapshot.hosts.to_vec());
+                all.retain(|host| host.source != source);
+                all.extend(hosts);
+                loaded.push((source.to_owned(), FileKind::Hosts));
+            }
+        }
+    }
+
+    let mut sightings = BTreeMap::new();
+    let leases = match new_leases {
+        Some(mut all) => {
+            all.sort_by_key(|lease| snapshot.source_order(&lease.source));
+            let all = Arc::new(all);
+            // The store compares against every lease it has, from all
+            // sources. The leases are still good if it fails.
+            let store = snapshot.store.clone();
+            match store.record_leases(all.clone(), snapshot.now()).await {
+                Ok(recorded) => {
+                    tracing::debug!("Recorded {} lease events", recorded.events);
+                    sightings = recorded.sightings;
+                }
+                Err(e) => tracing::error!("Failed to record lease history: {}", e),
+            }
+            all
+        }
+        None => snapshot.leases.clone(),
+    };
+    let hosts = match new_hosts {
+        Some(mut all) => {
+            all.sort_by_key(|host| snapshot.source_order(&host.source));
+            Arc::new(all)
+        }
+        None => snapshot.hosts.clone(),
+    };
+
+    install(db, loaded, leases, hosts, sightings).await
+}
+
+/// Build the lookup tables for `leases` and `hosts` and journal what changed
+/// in `files` on the blocking pool, then swap them all in. Only the file
+/// watcher changes leases and hosts, so nothing else can have replaced them
+/// in the meantime.
+async fn install(
+    db: &DB,
+    files: Vec<(String, FileKind)>,
+    leases: Arc<Vec<Lease>>,
+    hosts: Arc<Vec<Host>>,
+    sightings: BTreeMap<MacAddr, Sighting>,
+) -> Result<(), Error> {
+    let snapshot = db.load();
+    let (vendor_mapping, now) = (snapshot.vendor_mapping.clone(), snapshot.now());
+    let mut journal = Journal::clone(&snapshot.journal);
+    let (index, identities, journal) = {
+        let (leases, hosts) = (leases.clone(), hosts.clone());
+        tokio::task::spawn_blocking(move || {
+            let index = Index::build(&leases, &hosts, &vendor_mapping);
+            journal.record(&files, &leases, &hosts, now);
+            (index, Identities::build(&leases), journal)
+        })
+        .await?
+    };
+
+    db.update(|db| {
+        db.leases = leases;
+        db.hosts = hosts;
+        db.index = Arc::new(index);
+        db.identities = Arc::new(identities);
+        db.journal = Arc::new(journal);
+        if !sightings.is_empty() {
+            Arc::make_mut(&mut db.sightings).extend(sightings);
+        }
+    })
+    .await;
+
     Ok(())
 }
 
-pub async fn update_hosts<P>(db: DB, dhcpd_config: P) -> Result<(), Error>
-where
-    P: AsRef<Path>,
-{
-    let dhcpd_config = dhcpd_config.as_ref();
-    let buf = tokio::fs::read_to_string(dhcpd_config).await?;
-    let new_hosts = hosts::parse(&buf)?;
-    {
-        let mut db = db.lock().await;
-        db.hosts = new_hosts;
-        db.last_update_hosts.replace(Utc::now());
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use serde_json::json;
+
+    use super::*;
+    use crate::store::HistoryQuery;
+
+    const LEASE: &str = "lease 10.0.0.20 {
+  starts 1 2024/01/01 10:00:00;
+  ends 1 2024/01/02 10:00:00;
+  hardware ethernet f0:b3:ec:25:8c:2d;
+}
+";
+
+    fn leases_file(source: &str, path: &Path) -> SourceFile {
+        SourceFile {
+            source: source.to_owned(),
+            kind: FileKind::Leases,
+            path: path.to_owned(),
+        }
+    }
+
+    fn database() -> DB {
+        DB::new(Database::in_memory(&["lan", "lab"]))
+    }
+
+    #[tokio::test]
+    async fn test_status_keeps_responses() {
+        let db = database();
+        let now = db.load().now();
+        let cached = |db: &Database| {
+            db.responses
+                .get_or_insert("/".to_owned(), now, || Ok::<_, ()>(b"{}".to_vec()))
+                .unwrap()
+        };
+        let before = db.load();
+        let body = cached(&before);
+
+        db.update_status(|db| db.last_update_check = Some(now))
+            .await;
+        let after = db.load();
+        assert_eq!(after.last_update_check, Some(now));
+        assert_eq!(after.generation, before.generation);
+        assert!(Arc::ptr_eq(&body, &cached(&after)));
+    }
+
+    #[tokio::test]
+    async fn test_update_clears_responses() {
+        let db = database();
+        let now = db.load().now();
+        let cached = |db: &Database| {
+            db.responses
+                .get_or_insert("/".to_owned(), now, || Ok::<_, ()>(b"{}".to_vec()))
+                .unwrap()
+        };
+        let before = db.load();
+        let body = cached(&before);
+        assert!(Arc::ptr_eq(&body, &cached(&before)));
+
+        db.update(|_| ()).await;
+        let after = db.load();
+        assert_eq!(after.generation, before.generation + 1);
+        assert!(!Arc::ptr_eq(&body, &cached(&after)));
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_wait_for_changes() {
+        let dir = std::env::temp_dir().join(format!("dhcpd-api-notify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dhcpd.leases");
+        let file = leases_file("lan", &path);
+        let db = database();
+        let mut journaled = db.subscribe();
+
+        // The first load of a file is not journaled.
+        std::fs::write(&path, LEASE).unwrap();
+        reload(db.clone(), &file).await.unwrap();
+        let now = db.load().now();
+        db.update_status(|db| db.last_update_check = Some(now))
+            .await;
+        db.update(|_| ()).await;
+        assert!(!journaled.has_changed().unwrap());
+
+        std::fs::write(&path, LEASE.replace("2024/01/02", "2099/01/02")).unwrap();
+        reload(db.clone(), &file).await.unwrap();
+        assert!(journaled.has_changed().unwrap());
+        assert_eq!(*journaled.borrow_and_update(), db.load().journal.last_seq());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_expiry_without_reload() {
+        use chrono::TimeZone;
+
+        use crate::{changes::ChangeKind, clock::FixedClock};
+
+        let dir = std::env::temp_dir().join(format!("dhcpd-api-expiry-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dhcpd.leases");
+        let files = [leases_file("lan", &path)];
+        let at = |day| Utc.with_ymd_and_hms(2024, 1, day, 12, 0, 0).unwrap();
+        let db = database();
+        db.update(|db| db.clock = Arc::new(FixedClock(at(1)))).await;
+
+        // Loaded once before it began, so the lease's arrival is journaled.
+        std::fs::write(&path, LEASE.replace("2024/", "2023/")).unwrap();
+        reload(db.clone(), &files[0]).await.unwrap();
+        std::fs::write(&path, LEASE).unwrap();
+        reload(db.clone(), &files[0]).await.unwrap();
+        assert_eq!(db.load().journal.last_seq(), 1);
+
+        // The lease ends on the 2nd without the file changing.
+        let journaled = db.subscribe();
+        check_files(db.clone(), &files).await;
+        assert!(!journaled.has_changed().unwrap());
+        db.update(|db| db.clock = Arc::new(FixedClock(at(3)))).await;
+        check_files(db.clone(), &files).await;
+        assert!(journaled.has_changed().unwrap());
+        {
+            let db = db.load();
+            let left = db.journal.since(&db.journal.cursor(1)).collect::<Vec<_>>();
+            assert_eq!(left.len(), 1);
+            assert!(matches!(left[0].kind, ChangeKind::DeviceLeft { .. }));
+            assert_eq!(left[0].observed_at.at(), Some(at(3)));
+        }
+
+        // Only once.
+        check_files(db.clone(), &files).await;
+        assert_eq!(db.load().journal.last_seq(), 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reload_retries_and_keeps_last_good() {
+        let dir = std::env::temp_dir().join(format!("dhcpd-api-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dhcpd.leases");
+        let file = leases_file("lan", &path);
+        let db = database();
+
+        std::fs::write(&path, LEASE).unwrap();
+        reload(db.clone(), &file).await.unwrap();
+        let checksum = db.load().sources[0].leases.checksum.clone();
+        assert!(checksum.is_some());
+
+        // A torn write that is finished before the retries run out.
+        let torn = &LEASE[..40];
+        std::fs::write(&path, torn).unwrap();
+        let finish = {
+            let path = path.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(150)).await;
+                std::fs::write(&path, LEASE.replace("10.0.0.20", "10.0.0.21")).unwrap();
+            })
+        };
+        reload(db.clone(), &file).await.unwrap();
+        finish.await.unwrap();
+        {
+            let db = db.load();
+            assert_eq!(db.leases[0].address, Ipv4Addr::new(10, 0, 0, 21));
+            assert_eq!(db.sources[0].leases.consecutive_failures, 0);
+            assert!(db.sources[0].leases.last_error.is_none());
+            assert_ne!(db.sources[0].leases.checksum, checksum);
+        }
+
+        // One that never is: the previous leases stay.
+        std::fs::write(&path, torn).unwrap();
+        assert!(reload(db.clone(), &file).await.is_err());
+        {
+            let db = db.load();
+            assert_eq!(db.leases[0].address, Ipv4Addr::new(10, 0, 0, 21));
+            assert_eq!(db.sources[0].leases.consecutive_failures, 1);
+            assert!(db.sources[0].leases.last_error.is_some());
+            assert!(
+                db.sources[0].leases.last_success.at() < db.sources[0].leases.last_attempt.at()
+            );
+        }
+        assert!(reload(db.clone(), &file).await.is_err());
+        assert_eq!(db.load().sources[0].leases.consecutive_failures, 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reload_after_rotation() {
+        let dir = std::env::temp_dir().join(format!("dhcpd-api-rotate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dhcpd.leases");
+        let file = leases_file("lan", &path);
+        let db = database();
+
+        let active = LEASE
+            .replace("10.0.0.20", "10.0.0.30")
+            .replace("2024/01/02", "2099/01/02");
+        std::fs::write(&path, format!("{LEASE}{active}")).unwrap();
+        reload(db.clone(), &file).await.unwrap();
+        let loaded = db.load().sources[0].leases.file.clone().unwrap();
+        assert!(!file_changed(Some(&loaded), &path).await);
+
+        // dhcpd writes a new file, older than the one loaded, and moves it
+        // into place, keeping the old one as dhcpd.leases~.
+        let new = dir.join("dhcpd.leases.new");
+        std::fs::write(&new, LEASE).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&new)
+            .unwrap()
+            .set_modified(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap();
+        std::fs::rename(&path, dir.join("dhcpd.leases~")).unwrap();
+        std::fs::rename(&new, &path).unwrap();
+
+        assert!(file_changed(Some(&loaded), &path).await);
+        reload(db.clone(), &file).await.unwrap();
+        {
+            let db = db.load();
+            let addresses = db.leases.iter().map(|l| l.address).collect::<Vec<_>>();
+            assert_eq!(addresses, [Ipv4Addr::new(10, 0, 0, 20)]);
+            assert_ne!(db.sources[0].leases.file.as_ref(), Some(&loaded));
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sources() {
+        let dir = std::env::temp_dir().join(format!("dhcpd-api-sources-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let lan = leases_file("lan", &dir.join("lan.leases"));
+        let lab = leases_file("lab", &dir.join("lab.leases"));
+        let db = database();
+
+        std::fs::write(&lab.path, LEASE.replace("10.0.0.20", "10.9.0.1")).unwrap();
+        std::fs::write(&lan.path, LEASE).unwrap();
+        reload(db.clone(), &lab).await.unwrap();
+        reload(db.clone(), &lan).await.unwrap();
+
+        std::fs::write(&lan.path, LEASE.replace("10.0.0.20", "10.0.0.21")).unwrap();
+        reload(db.clone(), &lan).await.unwrap();
+        {
+            let db = db.load();
+            // Kept in command line order, and lab's lease survived the
+            // reload of lan.
+            let leases = db
+                .leases
+                .iter()
+                .map(|l| (l.source.as_str(), l.address))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                leases,
+                [
+                    ("lan", Ipv4Addr::new(10, 0, 0, 21)),
+                    ("lab", Ipv4Addr::new(10, 9, 0, 1)),
+                ]
+            );
+
+            // The same MAC is in both.
+            let mac = &db.leases[0].hardware_ethernet;
+            let all = json!(db.merged_device(mac, &SourceFilter::default()));
+            assert_eq!(all["sources"], json!(["lab", "lan"]));
+            assert_eq!(
+                db.last_success(FileKind::Hosts, &SourceFilter::default()),
+                None
+            );
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restart_with_sources() {
+        let dir = std::env::temp_dir().join(format!("dhcpd-api-restart-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let lan = leases_file("lan", &dir.join("lan.leases"));
+        let lab = leases_file("lab", &dir.join("lab.leases"));
+        std::fs::write(&lan.path, LEASE).unwrap();
+        std::fs::write(&lab.path, LEASE.replace("10.0.0.20", "10.9.0.1")).unwrap();
+        let files = [lan, lab];
+
+        let db = database();
+        load(&db, &files).await.unwrap();
+        let store = db.load().store.clone();
+
+        // A new process with the same store and the same files.
+        let mut restarted = Database::in_memory(&["lan", "lab"]);
+        restarted.store = store.clone();
+        load(&DB::new(restarted), &files).await.unwrap();
+
+        for ip in [Ipv4Addr::new(10, 0, 0, 20), Ipv4Addr::new(10, 9, 0, 1)] {
+            let events = store
+                .ip_history(ip, HistoryQuery::default())
+                .await
+                .unwrap()
+                .events;
+            assert_eq!(events.len(), 1, "{ip}: {events:?}");
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_clock() {
+        use chrono::TimeZone;
+
+        use crate::clock::FixedClock;
+
+        let at = |day, hour| Utc.with_ymd_and_hms(2024, 1, day, hour, 0, 0).unwrap();
+        let mut database = Database::in_memory(&["lan"]);
+        database.clock = Arc::new(FixedClock(at(2, 4)));
+        let db = DB::new(database);
+        update_leases(&db, "lan", leases::parse(LEASE).unwrap())
+            .await
+            .unwrap();
+        {
+            let db = db.load();
+            let device = json!(db.devices()[0]);
+            assert_eq!(device["lease"]["type"], "active");
+            assert_eq!(device["remaining_seconds"], 6 * 60 * 60);
+            assert_eq!(device["duration_seconds"], 24 * 60 * 60);
+        }
+
+        db.update(|db| db.clock = Arc::new(FixedClock(at(3, 4))))
+            .await;
+        let db = db.load();
+        let device = json!(db.devices()[0]);
+        assert_eq!(device["lease"]["type"], "expired");
+        assert_eq!(device["remaining_seconds"], 0);
     }
-    Ok(())
 }
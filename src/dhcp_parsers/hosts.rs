@@ -29,6 +29,9 @@ pub fn parse(input: &str) -> Result<Vec<Host>, ParseError> {
     let mut hosts = vec![];
 
     for item in items {
+        let Some(label) = item.label() else {
+            continue;
+        };
         let Some(fixed_address) = item.fixed_address() else {
             continue;
         };
@@ -37,9 +40,11 @@ pub fn parse(input: &str) -> Result<Vec<Host>, ParseError> {
         };
         let hostname = item.hostname();
         hosts.push(Host {
+            label: label.to_owned(),
             fixed_address,
             hardware_ethernet,
             hostname,
+            source: String::new(),
         });
     }
 
@@ -63,7 +68,6 @@ enum HostFileItem {
 }
 
 impl HostFileItem {
-    #[allow(dead_code)]
     fn label(&self) -> Option<&str> {
         match self {
             Self::Host { label, .. } => Some(label),
@@ -105,7 +109,7 @@ impl HostFileItem {
                 for field in fields {
                     if let HostField::Option(name, value) = field {
                         if name == "host-name" {
-                            return Some(value.to_string());
+                            return Some(value.clone());
                         }
                     }
                 }
@@ -1,4 +1,4 @@
-use std::net::Ipv4Addr;
+use std::{collections::BTreeSet, net::Ipv4Addr};
 
 use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use nom::{
@@ -6,14 +6,16 @@ use nom::{
     bytes,
     character::complete::{self, space0},
     combinator::{self, all_consuming},
-    multi::many1,
+    multi::{many1, separated_list1},
     sequence::{preceded, terminated},
     Finish, IResult,
 };
 
 use crate::model::{Lease, LeaseTime, MacAddr};
 
-use super::{anyspace0, anyspace1, keyword_hardware_ethernet, val_address, val_string};
+use super::{
+    anyspace0, anyspace1, keyword_hardware_ethernet, val_address, val_hexbyte, val_string,
+};
 
 #[derive(Debug, PartialEq)]
 enum LeaseFileItem {
@@ -38,7 +40,7 @@ enum LeaseField {
     Cltt(NaiveLeaseTime),
     HardwareEthernet(MacAddr),
     ClientHostname(String),
-    Uid(String),
+    Uid(Vec<u8>),
     VendorClassIdentifier(String),
     Ignore(String),
 }
@@ -67,6 +69,8 @@ pub fn parse(input: &str) -> Result<Vec<Lease>, ParseError> {
         let mut cltt: LeaseTime = None;
         let mut hardware_ethernet: Option<MacAddr> = None;
         let mut client_hostname: Option<String> = None;
+        let mut uid: Option<Vec<u8>> = None;
+        let mut vendor_class_identifier: Option<String> = None;
         if let LeaseFileItem::Lease(address, fields) = item {
             for field in fields {
                 match field {
@@ -84,7 +88,9 @@ pub fn parse(input: &str) -> Result<Vec<Lease>, ParseError> {
                     }
                     LeaseField::HardwareEthernet(addr) => hardware_ethernet = Some(addr),
                     LeaseField::ClientHostname(hostname) => client_hostname = Some(hostname),
-                    _ => {}
+                    LeaseField::Uid(id) => uid = Some(id),
+                    LeaseField::VendorClassIdentifier(vci) => vendor_class_identifier = Some(vci),
+                    LeaseField::Ignore(_) => {}
                 }
             }
 
@@ -96,11 +102,23 @@ pub fn parse(input: &str) -> Result<Vec<Lease>, ParseError> {
                 cltt,
                 hardware_ethernet: hardware_ethernet.ok_or(ParseError::MissingHardwareEthernet)?,
                 client_hostname,
+                uid,
+                vendor_class_identifier,
+                source: String::new(),
             };
             leases.push(lease);
         }
     }
 
+    // dhcpd appends a lease's block again when it changes without replacing
+    // the earlier one, and the last is current.
+    let mut seen = BTreeSet::new();
+    leases.reverse();
+    leases.retain(|lease| {
+        seen.insert((lease.address, lease.hardware_ethernet.clone(), lease.starts))
+    });
+    leases.reverse();
+
     Ok(leases)
 }
 
@@ -265,12 +283,21 @@ fn field_hardware_ethernet(input: &str) -> IResult<&str, LeaseField> {
     Ok((input, LeaseField::HardwareEthernet(mac)))
 }
 
+/// dhcpd writes the client identifier as a quoted string with octal escapes
+/// when it is mostly printable, and as colon separated hex octets otherwise.
 fn field_uid(input: &str) -> IResult<&str, LeaseField> {
     let (input, _) = bytes::complete::tag("uid")(input)?;
     let (input, _) = space0(input)?;
-    let (input, s) = val_string(input)?;
+    let (input, id) = alt((
+        combinator::map(val_string, |s| {
+            s.chars()
+                .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+                .collect()
+        }),
+        separated_list1(complete::char(':'), val_hexbyte),
+    ))(input)?;
 
-    Ok((input, LeaseField::Uid(s)))
+    Ok((input, LeaseField::Uid(id)))
 }
 
 fn field_client_hostname(input: &str) -> IResult<&str, LeaseField> {
@@ -321,3 +348,69 @@ fn field_binding_state(input: &str) -> IResult<&str, LeaseField> {
         )),
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use super::*;
+
+    #[test]
+    fn test_uid_forms() {
+        let (_, field) = field_uid(r#"uid "\001\360\263\354%\214-";"#).unwrap();
+        assert_eq!(
+            field,
+            LeaseField::Uid(vec![0x01, 0xf0, 0xb3, 0xec, 0x25, 0x8c, 0x2d])
+        );
+
+        let (_, field) = field_uid("uid ff:00:01:02;").unwrap();
+        assert_eq!(field, LeaseField::Uid(vec![0xff, 0x00, 0x01, 0x02]));
+    }
+
+    #[test]
+    fn test_parse_lease() {
+        let leases = parse(
+            r#"lease 10.0.0.20 {
+  starts 1 2024/01/01 10:00:00;
+  ends 1 2024/01/02 10:00:00;
+  cltt 1 2024/01/01 10:00:00;
+  binding state active;
+  next binding state free;
+  hardware ethernet f0:b3:ec:25:8c:2d;
+  uid "\001\360\263\354%\214-";
+  set vendor-class-identifier = "android-dhcp-13";
+  client-hostname "Living-Room-TV";
+}
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(leases.len(), 1);
+        assert_eq!(leases[0].client_hostname.as_deref(), Some("Living-Room-TV"));
+        assert_eq!(
+            leases[0].vendor_class_identifier.as_deref(),
+            Some("android-dhcp-13")
+        );
+        assert_eq!(leases[0].uid.as_ref().map(Vec::len), Some(7));
+    }
+
+    #[test]
+    fn test_repeated_blocks() {
+        let block = |address, hostname| {
+            format!(
+                "lease 10.0.0.{address} {{
+  starts 1 2024/01/01 10:00:00;
+  ends 1 2024/01/02 10:00:00;
+  hardware ethernet f0:b3:ec:25:8c:2d;
+  client-hostname \"{hostname}\";
+}}
+"
+            )
+        };
+        let file = [block(20, "old"), block(21, "other"), block(20, "new")].concat();
+        let leases = parse(&file).unwrap();
+
+        assert_eq!(leases.len(), 2);
+        assert_eq!(leases[0].address, Ipv4Addr::new(10, 0, 0, 21));
+        assert_eq!(leases[1].client_hostname.as_deref(), Some("new"));
+    }
+}
@@ -0,0 +1,111 @@
+use std::net::Ipv4Addr;
+
+use axum::{
+    body::Body,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+use crate::{cidr, db, listing, macaddr, render, search, source, store, webhooks};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("IPv6 not supported")]
+    Ipv6NotSupported,
+
+    #[error("Invalid IP address: {0}")]
+    InvalidIpAddr(#[from] std::net::AddrParseError),
+
+    #[error("Invalid MAC address: {0}")]
+    InvalidMacAddr(#[from] macaddr::InvalidMacAddr),
+
+    #[error("Not found")]
+    NotFound,
+
+    #[error("Origin not allowed")]
+    ForbiddenOrigin,
+
+    #[error("Unauthorized")]
+    Unauthorized,
+
+    #[error("Replacing every annotation with none; delete them one at a time instead")]
+    EmptyReplace,
+
+    #[error("Invalid CIDR: {0}")]
+    InvalidCidr(#[from] cidr::InvalidCidr),
+
+    #[error("Invalid range: {0} is after {1}")]
+    InvalidRange(Ipv4Addr, Ipv4Addr),
+
+    #[error("Invalid listing query: {0}")]
+    InvalidListing(#[from] listing::Error),
+
+    #[error("Invalid time: {0}")]
+    InvalidTime(String),
+
+    #[error("Invalid search: {0}")]
+    InvalidSearch(#[from] search::Error),
+
+    #[error("Invalid time format: {0}")]
+    InvalidFormat(#[from] render::Error),
+
+    #[error("Invalid source: {0}")]
+    InvalidSource(#[from] source::Error),
+
+    #[error("Database error: {0}")]
+    Database(#[from] db::Error),
+
+    #[error("Store error: {0}")]
+    Store(#[from] store::Error),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Webhooks error: {0}")]
+    Webhooks(#[from] webhooks::Error),
+
+    #[error("No --state-db given and no data directory to default to")]
+    NoStateDb,
+
+    #[error("Listen error: {0}")]
+    Listen(#[from] std::io::Error),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response<Body> {
+        if let Error::InvalidListing(listing::Error::Query(e)) = &self {
+            let body = json!({ "error": e });
+            return (StatusCode::BAD_REQUEST, Json(body)).into_response();
+        }
+
+        let resp = match self {
+            Error::Ipv6NotSupported => (StatusCode::BAD_REQUEST, "IPv6 not supported".to_string()),
+            Error::InvalidIpAddr(e) => (StatusCode::BAD_REQUEST, e.to_string()),
+            Error::InvalidMacAddr(e) => (StatusCode::BAD_REQUEST, e.to_string()),
+            Error::InvalidCidr(e) => (StatusCode::BAD_REQUEST, e.to_string()),
+            e @ (Error::InvalidRange(..) | Error::EmptyReplace) => {
+                (StatusCode::BAD_REQUEST, e.to_string())
+            }
+            Error::InvalidListing(listing::Error::Cursor(_)) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error".to_string(),
+            ),
+            Error::InvalidListing(e) => (StatusCode::BAD_REQUEST, e.to_string()),
+            Error::InvalidTime(e) => (StatusCode::BAD_REQUEST, e),
+            Error::InvalidSearch(e) => (StatusCode::BAD_REQUEST, e.to_string()),
+            Error::InvalidFormat(e) => (StatusCode::BAD_REQUEST, e.to_string()),
+            Error::InvalidSource(e) => (StatusCode::BAD_REQUEST, e.to_string()),
+            Error::NotFound => (StatusCode::NOT_FOUND, "Not found".to_string()),
+            Error::ForbiddenOrigin => (StatusCode::FORBIDDEN, "Origin not allowed".to_string()),
+            Error::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized".to_string()),
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error".to_string(),
+            ),
+        };
+
+        resp.into_response()
+    }
+}
@@ -0,0 +1,196 @@
+use std::{collections::VecDeque, convert::Infallible, time::Duration};
+
+use axum::response::sse::{Event, KeepAlive};
+use futures_util::{stream, Stream};
+use serde_json::json;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    changes::{ChangeFilter, Cursor},
+    db::DB,
+    render::TimeFormat,
+};
+
+/// How often to send a comment when nothing has changed, so that proxies
+/// don't close the connection.
+const HEARTBEAT: Duration = Duration::from_secs(15);
+
+pub fn keep_alive() -> KeepAlive {
+    KeepAlive::new().interval(HEARTBEAT).text("heartbeat")
+}
+
+struct Subscriber {
+    db: DB,
+    journaled: watch::Receiver<u64>,
+    shutdown: CancellationToken,
+    filter: ChangeFilter,
+    format: TimeFormat,
+    /// The last change looked at.
+    cursor: Cursor,
+    pending: VecDeque<Event>,
+}
+
+impl Subscriber {
+    /// Queue the changes after the cursor that pass the filter.
+    fn poll_journal(&mut self) {
+        let db = self.db.load();
+        for change in db.journal.since(&self.cursor) {
+            self.cursor = db.journal.cursor(change.seq);
+            if !self.filter.matches(change, &db.vendor_mapping) {
+                continue;
+            }
+            let event = Event::default()
+                .id(self.cursor.to_string())
+                .event(change.kind.action().name())
+                .json_data(self.format.apply(change.clone()));
+            match event {
+                Ok(event) => self.pending.push_back(event),
+                Err(e) => tracing::warn!("can't send change {}: {}", change.seq, e),
+            }
+        }
+    }
+}
+
+/// Device changes after `since` as Server-Sent Events, then each new change
+/// as soon as its reload is installed. Without `since`, only new changes are
+/// sent. If changes after `since` are no longer kept, or were numbered by an
+/// earlier process, a `missed` event comes first so the client can fetch `/`
+/// again, followed by new changes. Event ids are [`Cursor`]s.
+pub fn stream(
+    db: DB,
+    since: Option<Cursor>,
+    filter: ChangeFilter,
+    format: TimeFormat,
+    shutdown: CancellationToken,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    // Subscribe before reading the journal so no reload is missed.
+    let journaled = db.subscribe();
+    let journal = db.load().journal.clone();
+    let last = journal.cursor(journal.last_seq());
+    let mut pending = VecDeque::new();
+    let cursor = match since {
+        Some(since) if journal.missed(&since) => {
+            let data = json!({
+                "since": since.to_string(),
+                "epoch": journal.epoch(),
+                "last_seq": last.seq,
+            });
+            pending.push_back(
+                Event::default()
+                    .id(last.to_string())
+                    .event("missed")
+                    .data(data.to_string()),
+            );
+            last
+        }
+        Some(since) => since,
+        None => last,
+    };
+
+    let subscriber = Subscriber {
+        db,
+        journaled,
+        shutdown,
+        filter,
+        format,
+        cursor,
+        pending,
+    };
+
+    stream::unfold(subscriber, |mut subscriber| async move {
+        loop {
+            if let Some(event) = subscriber.pending.pop_front() {
+                return Some((Ok(event), subscriber));
+            }
+            subscriber.poll_journal();
+            if !subscriber.pending.is_empty() {
+                continue;
+            }
+            tokio::select! {
+                changed = subscriber.journaled.changed() => changed.ok()?,
+                () = subscriber.shutdown.cancelled() => return None,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use axum::response::{IntoResponse, Sse};
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{
+        changes::Journal,
+        db::{self, Database},
+        model::Lease,
+    };
+
+    /// The name and id of each event sent for `since`, up to the newest
+    /// change.
+    async fn events(db: &DB, since: Option<Cursor>) -> Vec<(String, Option<String>)> {
+        // Already shut down, so the stream ends once it has caught up.
+        let shutdown = CancellationToken::new();
+        shutdown.cancel();
+        let stream = stream(
+            db.clone(),
+            since,
+            ChangeFilter::default(),
+            TimeFormat::default(),
+            shutdown,
+        );
+        let body = Sse::new(stream).into_response().into_body();
+        let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        body.split_terminator("\n\n")
+            .map(|event| {
+                let field = |name: &str| {
+                    event
+                        .lines()
+                        .find_map(|line| line.strip_prefix(name))
+                        .map(str::to_owned)
+                };
+                (field("event: ").unwrap(), field("id: "))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_resume() {
+        let db = DB::new(Database::in_memory(&["lan"]));
+        for count in 1..=3 {
+            let leases = (1..=count)
+                .map(|d| Lease::test(d).active_at(Utc::now()))
+                .collect();
+            db::update_leases(&db, "lan", leases).await.unwrap();
+        }
+        assert_eq!(db.load().journal.last_seq(), 2);
+
+        let journal = db.load().journal.clone();
+        let id = |seq| Some(journal.cursor(seq).to_string());
+        let event = |name: &str, seq| (name.to_owned(), id(seq));
+        assert_eq!(
+            events(&db, Some(journal.cursor(0))).await,
+            [event("added", 1), event("added", 2)]
+        );
+        let resumed = events(&db, id(1).map(|id| id.parse().unwrap())).await;
+        assert_eq!(resumed, [event("added", 2)]);
+        assert!(events(&db, Some(journal.cursor(2))).await.is_empty());
+        assert!(events(&db, None).await.is_empty());
+
+        // Last-Event-IDs from before a restart, with a sequence number that
+        // is too new or one that is in range.
+        let restarted = Journal::default();
+        assert_eq!(
+            events(&db, Some(restarted.cursor(7))).await,
+            [event("missed", 2)]
+        );
+        assert_eq!(
+            events(&db, Some(restarted.cursor(1))).await,
+            [event("missed", 2)]
+        );
+    }
+}
@@ -0,0 +1,561 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    model::{Lease, LeaseTime, MacAddr},
+    render::{Render, Time, TimeFormat},
+};
+
+/// A stable signal that links two MAC addresses to the same device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Signal {
+    /// Both MACs sent the same client identifier (DHCP option 61).
+    ClientId,
+    /// Both MACs sent the same hostname and vendor class identifier.
+    HostnameAndVendorClass,
+    /// Both MACs sent the same hostname.
+    Hostname,
+}
+
+impl Signal {
+    /// How much a link on this signal alone is trusted.
+    pub fn confidence(self) -> f64 {
+        match self {
+            Self::ClientId => 0.95,
+            Self::HostnameAndVendorClass => 0.8,
+            Self::Hostname => 0.6,
+        }
+    }
+}
+
+/// One logical device, possibly seen under several MAC addresses.
+#[derive(Debug, Clone, Serialize)]
+pub struct Identity {
+    /// Derived from the device's client identifier or, without one, its
+    /// hostname or MAC, so it doesn't change as the device picks up new MACs.
+    pub id: String,
+    /// 1.0 for a single MAC, otherwise the confidence of the weakest link
+    /// that joined the cluster.
+    pub confidence: f64,
+    /// Every MAC the device has used, oldest first.
+    pub macs: Vec<MacAddr>,
+    pub hostnames: BTreeSet<String>,
+    pub client_ids: BTreeSet<String>,
+    pub vendor_class_identifiers: BTreeSet<String>,
+    pub signals: BTreeSet<Signal>,
+    pub first_seen: Time,
+    pub last_seen: Time,
+    #[serde(skip)]
+    pub leases: Vec<usize>,
+}
+
+impl Render for Identity {
+    fn render(&mut self, format: TimeFormat) {
+        self.first_seen.render(format);
+        self.last_seen.render(format);
+    }
+}
+
+/// Leases clustered into identities, rebuilt whenever the leases change.
+#[derive(Debug, Clone, Default)]
+pub struct Identities {
+    identities: Vec<Identity>,
+    by_mac: BTreeMap<MacAddr, usize>,
+}
+
+/// Everything known about one MAC address.
+struct MacInfo<'a> {
+    mac: &'a MacAddr,
+    leases: Vec<usize>,
+    /// `[starts, cltt]` of each lease, sorted, used to reject merging MACs
+    /// that were in use at the same time.
+    intervals: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+    first_seen: LeaseTime,
+    client_ids: BTreeSet<&'a [u8]>,
+    hostnames: BTreeSet<String>,
+    vendor_classes: BTreeSet<&'a str>,
+}
+
+/// What MACs are bucketed by; only MACs sharing a bucket can be linked.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Key<'a> {
+    ClientId(&'a [u8]),
+    Hostname(&'a str),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Link {
+    a: usize,
+    b: usize,
+    signal: Signal,
+}
+
+impl Identities {
+    pub fn build(leases: &[Lease]) -> Self {
+        let mut by_mac = BTreeMap::<&MacAddr, Vec<usize>>::new();
+        for (i, lease) in leases.iter().enumerate() {
+            by_mac.entry(&lease.hardware_ethernet).or_default().push(i);
+        }
+        let macs = by_mac
+            .into_iter()
+            .map(|(mac, indexes)| MacInfo::new(mac, indexes, leases))
+            .collect::<Vec<_>>();
+
+        let mut clusters = Clusters::new(&macs);
+        for link in links(&macs) {
+            clusters.union(link);
+        }
+
+        let mut identities = clusters
+            .into_members()
+            .into_iter()
+            .map(|(members, signals)| Identity::new(&members, signals, &macs, leases))
+            .collect::<Vec<_>>();
+        assign_ids(&mut identities);
+        identities.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let by_mac = identities
+            .iter()
+            .enumerate()
+            .flat_map(|(i, identity)| identity.macs.iter().map(move |mac| (mac.clone(), i)))
+            .collect();
+
+        Self { identities, by_mac }
+    }
+
+    pub fn all(&self) -> &[Identity] {
+        &self.identities
+    }
+
+    pub fn by_mac(&self, mac: &MacAddr) -> Option<&Identity> {
+        self.by_mac.get(mac).map(|&i| &self.identities[i])
+    }
+
+    pub fn by_id(&self, id: &str) -> Option<&Identity> {
+        self.identities.iter().find(|identity| identity.id == id)
+    }
+}
+
+impl<'a> MacInfo<'a> {
+    fn new(mac: &'a MacAddr, leases: Vec<usize>, all: &'a [Lease]) -> Self {
+        let mut info = Self {
+            mac,
+            leases: Vec::new(),
+            intervals: Vec::new(),
+            first_seen: None,
+            client_ids: BTreeSet::new(),
+            hostnames: BTreeSet::new(),
+            vendor_classes: BTreeSet::new(),
+        };
+        for &i in &leases {
+            let lease = &all[i];
+            if let Some(starts) = lease.starts {
+                let seen = lease.cltt.map_or(starts, |cltt| cltt.max(starts));
+                info.intervals.push((starts, seen));
+            }
+            info.first_seen = earliest(info.first_seen, lease.starts);
+            if let Some(uid) = lease.uid.as_deref().filter(|uid| !is_mac_derived(uid, mac)) {
+                info.client_ids.insert(uid);
+            }
+            if let Some(hostname) = &lease.client_hostname {
+                info.hostnames.insert(hostname.to_lowercase());
+            }
+            if let Some(vci) = &lease.vendor_class_identifier {
+                info.vendor_classes.insert(vci);
+            }
+        }
+        info.intervals.sort_unstable();
+        info.leases = leases;
+        info
+    }
+
+    fn keys(&self) -> impl Iterator<Item = Key<'_>> {
+        let client_ids = self.client_ids.iter().map(|&uid| Key::ClientId(uid));
+        let hostnames = self
+            .hostnames
+            .iter()
+            .map(|hostname| Key::Hostname(hostname));
+        client_ids.chain(hostnames)
+    }
+
+    /// The strongest signal shared with `other`, if any.
+    fn signal(&self, other: &Self) -> Option<Signal> {
+        if !self.client_ids.is_disjoint(&other.client_ids) {
+            Some(Signal::ClientId)
+        } else if self.hostnames.is_disjoint(&other.hostnames) {
+            None
+        } else if !self.vendor_classes.is_disjoint(&other.vendor_classes) {
+            Some(Signal::HostnameAndVendorClass)
+        } else {
+            Some(Signal::Hostname)
+        }
+    }
+}
+
+fn earliest(a: LeaseTime, b: LeaseTime) -> LeaseTime {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// A client identifier of type 1 (ethernet) followed by the MAC itself says
+/// nothing beyond the MAC, so it can't link two MACs together.
+fn is_mac_derived(uid: &[u8], mac: &MacAddr) -> bool {
+    uid.split_first() == Some((&1, mac.bytes()))
+}
+
+/// Whether two sorted lists of intervals have any in common.
+fn overlaps<T: Ord>(a: &[(T, T)], b: &[(T, T)]) -> bool {
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].0 <= b[j].1 && b[j].0 <= a[i].1 {
+            return true;
+        }
+        // Whichever ends first can't overlap anything later in the other.
+        if a[i].1 < b[j].1 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    false
+}
+
+/// Hostnames sent by more MACs than this, such as `android` or `iphone`,
+/// are too common to link MACs by, and comparing every pair of them would
+/// be quadratic. Those MACs can still be linked by client identifier.
+const HOSTNAME_BUCKET_LIMIT: usize = 64;
+
+/// Candidate links between MACs that share a signal, strongest first and,
+/// within a signal, the shortest hand-over between the two MACs first.
+///
+/// MACs are only compared with others sharing a client identifier or
+/// hostname, so unrelated MACs cost nothing.
+fn links(macs: &[MacInfo]) -> Vec<Link> {
+    let mut buckets = BTreeMap::<Key, Vec<usize>>::new();
+    for (i, info) in macs.iter().enumerate() {
+        for key in info.keys() {
+            buckets.entry(key).or_default().push(i);
+        }
+    }
+    buckets.retain(|key, bucket| {
+        matches!(key, Key::ClientId(_)) || bucket.len() <= HOSTNAME_BUCKET_LIMIT
+    });
+
+    let mut links = BTreeSet::new();
+    for bucket in buckets.values() {
+        for (n, &a) in bucket.iter().enumerate() {
+            for &b in &bucket[n + 1..] {
+                if let Some(signal) = macs[a].signal(&macs[b]) {
+                    links.insert(Link { a, b, signal });
+                }
+            }
+        }
+    }
+    let mut links = links.into_iter().collect::<Vec<_>>();
+
+    let gap = |link: &Link| match (macs[link.a].first_seen, macs[link.b].first_seen) {
+        (Some(a), Some(b)) => Some((a - b).abs()),
+        _ => None,
+    };
+    links.sort_by_key(|link| {
+        let gap = gap(link);
+        (link.signal, gap.is_none(), gap, link.a, link.b)
+    });
+    links
+}
+
+type Interval = (DateTime<Utc>, DateTime<Utc>);
+
+/// Union-find over MAC indexes that refuses to join clusters whose MACs
+/// were in use at the same time.
+struct Clusters {
+    parent: Vec<usize>,
+    members: Vec<Vec<usize>>,
+    /// The sorted intervals of every MAC in each cluster.
+    intervals: Vec<Vec<Interval>>,
+    signals: Vec<BTreeSet<Signal>>,
+}
+
+impl Clusters {
+    fn new(macs: &[MacInfo]) -> Self {
+        Self {
+            parent: (0..macs.len()).collect(),
+            members: (0..macs.len()).map(|i| vec![i]).collect(),
+            intervals: macs.iter().map(|info| info.intervals.clone()).collect(),
+            signals: vec![BTreeSet::new(); macs.len()],
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    fn union(&mut self, link: Link) {
+        let (a, b) = (self.find(link.a), self.find(link.b));
+        if a == b {
+            return;
+        }
+        if overlaps(&self.intervals[a], &self.intervals[b]) {
+            return;
+        }
+
+        self.parent[b] = a;
+        let members = std::mem::take(&mut self.members[b]);
+        self.members[a].extend(members);
+        let intervals = std::mem::take(&mut self.intervals[b]);
+        self.intervals[a].extend(intervals);
+        self.intervals[a].sort_unstable();
+        let signals = std::mem::take(&mut self.signals[b]);
+        self.signals[a].extend(signals);
+        self.signals[a].insert(link.signal);
+    }
+
+    fn into_members(self) -> Vec<(Vec<usize>, BTreeSet<Signal>)> {
+        self.members
+            .into_iter()
+            .zip(self.signals)
+            .filter(|(members, _)| !members.is_empty())
+            .collect()
+    }
+}
+
+impl Identity {
+    fn new(
+        members: &[usize],
+        signals: BTreeSet<Signal>,
+        macs: &[MacInfo],
+        leases: &[Lease],
+    ) -> Self {
+        let mut members = members.iter().map(|&i| &macs[i]).collect::<Vec<_>>();
+        // Unknown start times sort last.
+        members.sort_by_key(|info| (info.first_seen.is_none(), info.first_seen, info.mac));
+
+        let confidence = signals
+            .iter()
+            .map(|signal| signal.confidence())
+            .fold(1.0, f64::min);
+        let mut identity = Self {
+            id: id(&members),
+            confidence,
+            macs: members.iter().map(|info| info.mac.clone()).collect(),
+            hostnames: BTreeSet::new(),
+            client_ids: BTreeSet::new(),
+            vendor_class_identifiers: BTreeSet::new(),
+            signals,
+            first_seen: Time::default(),
+            last_seen: Time::default(),
+            leases: members
+                .iter()
+                .flat_map(|info| info.leases.iter().copied())
+                .collect(),
+        };
+        for &i in &identity.leases {
+            let lease = &leases[i];
+            if let Some(hostname) = &lease.client_hostname {
+                identity.hostnames.insert(hostname.clone());
+            }
+            if let Some(uid) = &lease.uid {
+                let hex = uid.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>();
+                identity.client_ids.insert(hex.join(":"));
+            }
+            if let Some(vci) = &lease.vendor_class_identifier {
+                identity.vendor_class_identifiers.insert(vci.clone());
+            }
+            identity.first_seen = earliest(identity.first_seen.at(), lease.starts).into();
+            identity.last_seen = (identity.last_seen.at())
+                .max(lease.cltt.or(lease.starts))
+                .into();
+        }
+
+        identity
+    }
+}
+
+/// An id from the cluster's smallest client identifier, else its smallest
+/// hostname, else its MAC. Those are what linked the cluster together, so
+/// the id survives the device moving to a new MAC.
+fn id(members: &[&MacInfo]) -> String {
+    let mut hash = Sha256::new();
+    if let Some(uid) = members
+        .iter()
+        .filter_map(|info| info.client_ids.first())
+        .min()
+    {
+        hash.update(b"uid:");
+        hash.update(uid);
+    } else if let Some(hostname) = members
+        .iter()
+        .filter_map(|info| info.hostnames.first())
+        .min()
+    {
+        hash.update(b"hostname:");
+        hash.update(hostname);
+    } else {
+        hash.update(b"mac:");
+        hash.update(members[0].mac.bytes());
+    }
+
+    let mut id = String::from("dev-");
+    for byte in &hash.finalize()[..6] {
+        let _ = write!(id, "{byte:02x}");
+    }
+    id
+}
+
+/// Devices that were never linked can still share a key, such as two phones
+/// both called "iPhone". The one seen first keeps the plain id and the rest
+/// are numbered in the order they were first seen.
+fn assign_ids(identities: &mut [Identity]) {
+    let mut order = (0..identities.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| {
+        let (a, b) = (&identities[a], &identities[b]);
+        let key = |identity: &Identity| {
+            let first_seen = identity.first_seen.at();
+            (first_seen.is_none(), first_seen)
+        };
+        (&a.id, key(a), &a.macs).cmp(&(&b.id, key(b), &b.macs))
+    });
+
+    let mut seen = BTreeMap::<String, usize>::new();
+    for i in order {
+        let count = seen.entry(identities[i].id.clone()).or_default();
+        *count += 1;
+        if *count > 1 {
+            let _ = write!(identities[i].id, "-{count}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use super::*;
+    use chrono::TimeZone;
+
+    /// A day's lease of `mac`, seen halfway through.
+    fn lease(mac: [u8; 6], day: u32, hostname: &str) -> Lease {
+        let starts = Utc.with_ymd_and_hms(2024, 1, day, 8, 0, 0).unwrap();
+        Lease::test(1)
+            .with_mac(mac)
+            .with_hostname(hostname)
+            .with_times(starts, starts + chrono::Duration::days(1))
+            .with_cltt(starts + chrono::Duration::hours(12))
+    }
+
+    #[test]
+    fn test_client_id_links_randomized_macs() {
+        let uid: &[u8] = b"\xffphone-duid";
+        let leases = vec![
+            lease([0x02, 0, 0, 0, 0, 1], 1, "Pixel-7").with_uid(uid),
+            lease([0x06, 0, 0, 0, 0, 2], 3, "android-1234").with_uid(uid),
+            lease([0x10, 0x20, 0x30, 0, 0, 3], 2, "laptop"),
+        ];
+        let identities = Identities::build(&leases);
+        assert_eq!(identities.all().len(), 2);
+
+        let phone = identities
+            .by_mac(&MacAddr::from([0x06, 0, 0, 0, 0, 2]))
+            .unwrap();
+        assert_eq!(phone.macs.len(), 2);
+        assert!((phone.confidence - 0.95).abs() < f64::EPSILON);
+        assert_eq!(phone.signals, BTreeSet::from([Signal::ClientId]));
+        assert_eq!(phone.first_seen.at(), leases[0].starts);
+        assert_eq!(phone.last_seen.at(), leases[1].cltt);
+
+        let laptop = identities
+            .by_mac(&MacAddr::from([0x10, 0x20, 0x30, 0, 0, 3]))
+            .unwrap();
+        assert!((laptop.confidence - 1.0).abs() < f64::EPSILON);
+        assert_eq!(identities.by_id(&laptop.id).unwrap().macs, laptop.macs);
+    }
+
+    #[test]
+    fn test_id_survives_new_macs() {
+        let uid: &[u8] = b"\xffphone-duid";
+        let mut leases = vec![lease([0x02, 0, 0, 0, 0, 1], 1, "Pixel-7").with_uid(uid)];
+        let before = Identities::build(&leases).all()[0].id.clone();
+        assert!(before.starts_with("dev-"));
+
+        // A new MAC joins the cluster, and then the first one ages out.
+        leases.push(lease([0x06, 0, 0, 0, 0, 2], 3, "Pixel-7").with_uid(uid));
+        assert_eq!(Identities::build(&leases).all()[0].id, before);
+        leases.remove(0);
+        assert_eq!(Identities::build(&leases).all()[0].id, before);
+    }
+
+    #[test]
+    fn test_mac_derived_client_id_is_ignored() {
+        let a = [0x02, 0, 0, 0, 0, 1];
+        let leases = vec![
+            lease(a, 1, "a").with_uid(&[1, 0x02, 0, 0, 0, 0, 1]),
+            lease([0x02, 0, 0, 0, 0, 2], 2, "b").with_uid(&[1, 0x02, 0, 0, 0, 0, 1]),
+        ];
+        // A client id built from the MAC is only a signal on other MACs.
+        let identities = Identities::build(&leases);
+        assert_eq!(identities.all().len(), 2);
+    }
+
+    #[test]
+    fn test_hostname_links_respect_overlap() {
+        let leases = vec![
+            lease([0x02, 0, 0, 0, 0, 1], 1, "iPhone").with_vendor_class("ios"),
+            lease([0x02, 0, 0, 0, 0, 2], 1, "iphone").with_vendor_class("ios"),
+            lease([0x02, 0, 0, 0, 0, 3], 2, "iPhone"),
+        ];
+        let identities = Identities::build(&leases);
+        // The first two were in use at the same time, so they can't be the
+        // same device; the third joins the first by hostname alone.
+        assert_eq!(identities.all().len(), 2);
+        let first = identities
+            .by_mac(&MacAddr::from([0x02, 0, 0, 0, 0, 1]))
+            .unwrap();
+        assert_eq!(first.macs.len(), 2);
+        assert!((first.confidence - 0.6).abs() < f64::EPSILON);
+
+        // Both are keyed by the same hostname; the later one is numbered.
+        let second = identities
+            .by_mac(&MacAddr::from([0x02, 0, 0, 0, 0, 2]))
+            .unwrap();
+        assert_eq!(second.id, format!("{}-2", first.id));
+    }
+
+    #[test]
+    fn test_common_hostname_falls_back_to_client_id() {
+        let base = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let uid: &[u8] = b"\xffphone-duid";
+        let mut leases = (0..=HOSTNAME_BUCKET_LIMIT)
+            .map(|i| {
+                let starts = base + chrono::Duration::hours(2 * i64::try_from(i).unwrap());
+                Lease::test(1)
+                    .with_mac([0x02, 0, 0, 0, 0, u8::try_from(i).unwrap()])
+                    .with_hostname("android")
+                    .with_times(starts, starts + chrono::Duration::hours(1))
+                    .with_cltt(starts)
+            })
+            .collect::<Vec<_>>();
+        leases[0] = leases[0].clone().with_uid(uid);
+        leases[1] = leases[1].clone().with_uid(uid);
+
+        // Too many MACs send the hostname to link by it, but the two that
+        // share a client id are still one device.
+        let identities = Identities::build(&leases);
+        assert_eq!(identities.all().len(), HOSTNAME_BUCKET_LIMIT);
+        let phone = identities
+            .by_mac(&MacAddr::from([0x02, 0, 0, 0, 0, 1]))
+            .unwrap();
+        assert_eq!(phone.signals, BTreeSet::from([Signal::ClientId]));
+    }
+}
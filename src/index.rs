@@ -0,0 +1,256 @@
+use std::{collections::BTreeMap, net::Ipv4Addr, ops::RangeInclusive};
+
+use radix_trie::{Trie, TrieCommon};
+
+use crate::{
+    macaddr::MacPrefix,
+    model::{Host, Lease, MacAddr},
+    vendor_macs::VendorMapping,
+};
+
+/// Positions into `Database::leases` and `Database::hosts` that share a key.
+#[derive(Debug, Clone, Default)]
+pub struct Entries {
+    pub leases: Vec<usize>,
+    pub hosts: Vec<usize>,
+}
+
+/// Lookup tables built once per reload, so requests never scan every lease.
+#[derive(Debug, Clone, Default)]
+pub struct Index {
+    lease_vendors: Vec<Option<String>>,
+    host_vendors: Vec<Option<String>>,
+
+    by_ip: BTreeMap<Ipv4Addr, Entries>,
+    by_mac: BTreeMap<MacAddr, Entries>,
+    mac_trie: Trie<MacPrefix, MacAddr>,
+    by_hostname: BTreeMap<String, Entries>,
+    by_label: BTreeMap<String, Entries>,
+    by_vendor: BTreeMap<String, Entries>,
+}
+
+impl Index {
+    pub fn build(leases: &[Lease], hosts: &[Host], vendor_mapping: &VendorMapping) -> Self {
+        let mut index = Self {
+            lease_vendors: Vec::with_capacity(leases.len()),
+            host_vendors: Vec::with_capacity(hosts.len()),
+            ..Self::default()
+        };
+
+        for (i, lease) in leases.iter().enumerate() {
+            let vendor = vendor_mapping
+                .get_vendor_name(&lease.hardware_ethernet)
+                .map(str::to_owned);
+
+            index.by_ip.entry(lease.address).or_default().leases.push(i);
+            index
+                .by_mac
+                .entry(lease.hardware_ethernet.clone())
+                .or_default()
+                .leases
+                .push(i);
+            if let Some(hostname) = &lease.client_hostname {
+                index
+                    .by_hostname
+                    .entry(hostname.to_lowercase())
+                    .or_default()
+                    .leases
+                    .push(i);
+            }
+            if let Some(vendor) = &vendor {
+                index
+                    .by_vendor
+                    .entry(vendor.clone())
+                    .or_default()
+                    .leases
+                    .push(i);
+            }
+            index.lease_vendors.push(vendor);
+        }
+
+        for (i, host) in hosts.iter().enumerate() {
+            let vendor = vendor_mapping
+                .get_vendor_name(&host.hardware_ethernet)
+                .map(str::to_owned);
+
+            index
+                .by_ip
+                .entry(host.fixed_address)
+                .or_default()
+                .hosts
+                .push(i);
+            index
+                .by_mac
+                .entry(host.hardware_ethernet.clone())
+                .or_default()
+                .hosts
+                .push(i);
+            if let Some(hostname) = &host.hostname {
+                index
+                    .by_hostname
+                    .entry(hostname.to_lowercase())
+                    .or_default()
+                    .hosts
+                    .push(i);
+            }
+            index
+                .by_label
+                .entry(host.label.to_lowercase())
+                .or_default()
+                .hosts
+                .push(i);
+            if let Some(vendor) = &vendor {
+                index
+                    .by_vendor
+                    .entry(vendor.clone())
+                    .or_default()
+                    .hosts
+                    .push(i);
+            }
+            index.host_vendors.push(vendor);
+        }
+
+        for mac in index.by_mac.keys() {
+            index.mac_trie.insert(mac.into(), mac.clone());
+        }
+
+        index
+    }
+
+    pub fn lease_vendor(&self, i: usize) -> Option<&str> {
+        self.lease_vendors.get(i)?.as_deref()
+    }
+
+    pub fn host_vendor(&self, i: usize) -> Option<&str> {
+        self.host_vendors.get(i)?.as_deref()
+    }
+
+    pub fn by_ip(&self, ip: Ipv4Addr) -> Option<&Entries> {
+        self.by_ip.get(&ip)
+    }
+
+    /// Entries whose address falls in `range`, in ascending address order.
+    pub fn ip_range(
+        &self,
+        range: RangeInclusive<Ipv4Addr>,
+    ) -> impl Iterator<Item = (&Ipv4Addr, &Entries)> {
+        self.by_ip.range(range)
+    }
+
+    pub fn by_mac(&self, mac: &MacAddr) -> Option<&Entries> {
+        self.by_mac.get(mac)
+    }
+
+    /// Entries for every MAC address starting with `prefix`, sorted by MAC.
+    pub fn by_mac_prefix(&self, prefix: &MacPrefix) -> Vec<&Entries> {
+        let Some(subtrie) = self.mac_trie.get_raw_descendant(prefix) else {
+            return Vec::new();
+        };
+        let mut macs = subtrie
+            .values()
+            .filter(|mac| prefix.matches(mac))
+            .collect::<Vec<_>>();
+        macs.sort();
+
+        macs.into_iter()
+            .filter_map(|mac| self.by_mac(mac))
+            .collect()
+    }
+
+    pub fn by_mac_entry(&self, mac: &MacAddr) -> Option<(&MacAddr, &Entries)> {
+        self.by_mac.get_key_value(mac)
+    }
+
+    pub fn macs(&self) -> impl Iterator<Item = (&MacAddr, &Entries)> {
+        self.by_mac.iter()
+    }
+
+    /// Every lowercased client hostname, static hostname and host label.
+    pub fn names(&self) -> impl Iterator<Item = (&str, &Entries)> {
+        self.by_hostname
+            .iter()
+            .chain(self.by_label.iter())
+            .map(|(name, entries)| (name.as_str(), entries))
+    }
+
+    /// Every known vendor name, sorted.
+    pub fn vendors(&self) -> impl Iterator<Item = &str> {
+        self.by_vendor.keys().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use super::*;
+
+    fn host(address: [u8; 4], mac: [u8; 6], hostname: Option<&str>) -> Host {
+        Host {
+            label: format!("host_{}", address[3]),
+            fixed_address: Ipv4Addr::from(address),
+            hardware_ethernet: MacAddr::from(mac),
+            hostname: hostname.map(str::to_owned),
+            source: String::new(),
+        }
+    }
+
+    fn vendor_mapping() -> VendorMapping {
+        VendorMapping::parse(
+            r#"<MacAddressVendorMappings>
+<VendorMapping mac_prefix="10:20:30" vendor_name="Acme"/>
+</MacAddressVendorMappings>"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_index_lookups() {
+        let leases = vec![
+            Lease::test(2)
+                .with_mac([0x10, 0x20, 0x30, 0, 0, 1])
+                .with_hostname("Laptop"),
+            Lease::test(3).with_mac([0xaa, 0xbb, 0xcc, 0, 0, 2]),
+            Lease::test(2)
+                .with_mac([0xaa, 0xbb, 0xcc, 0, 0, 2])
+                .with_hostname("phone"),
+        ];
+        let hosts = vec![host(
+            [10, 0, 0, 2],
+            [0x10, 0x20, 0x30, 0, 0, 1],
+            Some("laptop"),
+        )];
+        let index = Index::build(&leases, &hosts, &vendor_mapping());
+
+        let entries = index.by_ip(Ipv4Addr::new(10, 0, 0, 2)).unwrap();
+        assert_eq!(entries.leases, vec![0, 2]);
+        assert_eq!(entries.hosts, vec![0]);
+
+        let entries = index
+            .by_mac(&MacAddr::from([0xaa, 0xbb, 0xcc, 0, 0, 2]))
+            .unwrap();
+        assert_eq!(entries.leases, vec![1, 2]);
+        assert!(entries.hosts.is_empty());
+
+        let entries = &index.by_hostname["laptop"];
+        assert_eq!(entries.leases, vec![0]);
+        assert_eq!(entries.hosts, vec![0]);
+
+        assert_eq!(index.lease_vendor(0), Some("Acme"));
+        assert_eq!(index.lease_vendor(1), None);
+        assert_eq!(index.host_vendor(0), Some("Acme"));
+        assert_eq!(index.vendors().collect::<Vec<_>>(), vec!["Acme"]);
+        assert_eq!(index.by_vendor["Acme"].leases, vec![0]);
+        assert!(index.by_ip(Ipv4Addr::new(10, 0, 0, 9)).is_none());
+
+        let prefix = "aa:bb:cc".parse::<MacPrefix>().unwrap();
+        let entries = index.by_mac_prefix(&prefix);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].leases, vec![1, 2]);
+
+        let prefix = "10".parse::<MacPrefix>().unwrap();
+        assert_eq!(index.by_mac_prefix(&prefix).len(), 1);
+
+        let prefix = "aa:bb:cd".parse::<MacPrefix>().unwrap();
+        assert!(index.by_mac_prefix(&prefix).is_empty());
+    }
+}
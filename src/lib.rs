@@ -0,0 +1,42 @@
+#![warn(
+    clippy::all,
+    clippy::pedantic,
+    clippy::unwrap_used,
+    clippy::expect_used
+)]
+// The server's modules, shared by the binary and the benchmarks rather than
+// published, so the lints for documenting a library's API don't apply.
+#![allow(
+    clippy::must_use_candidate,
+    clippy::return_self_not_must_use,
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
+    clippy::new_without_default
+)]
+
+pub mod args;
+pub mod auth;
+pub mod cache;
+pub mod changes;
+pub mod cidr;
+pub mod clock;
+pub mod db;
+pub mod dhcp_parsers;
+mod error;
+pub mod events;
+pub mod identity;
+pub mod index;
+pub mod listing;
+pub mod macaddr;
+pub mod model;
+pub mod query;
+pub mod render;
+pub mod search;
+pub mod source;
+pub mod store;
+pub mod vendor_macs;
+pub mod watch;
+pub mod webhooks;
+pub mod ws;
+
+pub use error::Error;
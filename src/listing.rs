@@ -0,0 +1,527 @@
+use std::{cmp::Ordering, fmt::Write, net::Ipv4Addr, str::FromStr};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    model::{Device, LeaseTime, LeaseTiming, MacAddr, MacKind},
+    query::{self, Expr},
+    render::{Render, TimeFormat},
+};
+
+const LEASE_TYPES: [&str; 3] = ["active", "expired", "static"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unknown lease type: {0}")]
+    UnknownLeaseType(String),
+
+    #[error("unknown mac kind: {0}")]
+    UnknownMacKind(String),
+
+    #[error("invalid expiring_within: {0}")]
+    InvalidDuration(String),
+
+    #[error("invalid time: {0}")]
+    InvalidTime(String),
+
+    #[error("invalid cursor")]
+    InvalidCursor,
+
+    #[error("can't encode cursor: {0}")]
+    Cursor(#[source] serde_json::Error),
+
+    #[error("invalid query: {0}")]
+    Query(#[from] query::ParseError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    Ip,
+    Mac,
+    Hostname,
+    Vendor,
+    LastSeen,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    fn apply(self, ordering: Ordering) -> Ordering {
+        match self {
+            Self::Asc => ordering,
+            Self::Desc => ordering.reverse(),
+        }
+    }
+}
+
+/// Query parameters accepted by device listings.
+#[derive(Debug, Default, Deserialize)]
+pub struct ListQuery {
+    /// An expression in the query language, see [`crate::query`].
+    pub q: Option<String>,
+
+    /// Comma separated lease types: `active`, `expired` and/or `static`.
+    #[serde(rename = "type")]
+    pub lease_type: Option<String>,
+
+    /// Comma separated MAC kinds, see [`MacKind::NAMES`]. `local` finds
+    /// randomized addresses.
+    pub mac_kind: Option<String>,
+
+    /// Exact vendor name, ignoring case.
+    pub vendor: Option<String>,
+
+    pub has_hostname: Option<bool>,
+
+    pub seen_after: Option<DateTime<Utc>>,
+    pub seen_before: Option<DateTime<Utc>>,
+
+    /// Only active leases that end within this long, such as `30m` or `2h`.
+    pub expiring_within: Option<String>,
+
+    /// Without `sort`, devices keep file order unless paginating, which
+    /// sorts by `ip`.
+    pub sort: Option<SortField>,
+
+    #[serde(default)]
+    pub order: SortOrder,
+
+    pub limit: Option<usize>,
+
+    /// The `next_cursor` from the previous page.
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<'a> {
+    pub devices: Vec<Device<'a>>,
+    pub next_cursor: Option<String>,
+}
+
+impl Render for Page<'_> {
+    fn render(&mut self, format: TimeFormat) {
+        self.devices.render(format);
+    }
+}
+
+impl ListQuery {
+    pub fn apply<'a>(
+        &self,
+        devices: Vec<Device<'a>>,
+        now: DateTime<Utc>,
+    ) -> Result<Page<'a>, Error> {
+        let lease_types = self.lease_types()?;
+        let mac_kinds = self.mac_kinds()?;
+        let expiring_within = self
+            .expiring_within
+            .as_deref()
+            .map(query::parse_duration)
+            .transpose()
+            .map_err(Error::InvalidDuration)?;
+        let expr = self.q.as_deref().map(Expr::from_str).transpose()?;
+        if let Some(expr) = &expr {
+            expr.check_times(now).map_err(Error::InvalidTime)?;
+        }
+        let devices = devices
+            .into_iter()
+            .filter(|device| self.matches(device, &lease_types, &mac_kinds))
+            .filter(|device| match expiring_within {
+                Some(within) => expires_within(device, within),
+                None => true,
+            })
+            .filter(|device| match &expr {
+                Some(expr) => expr.matches(device, now),
+                None => true,
+            });
+
+        let paginate = self.limit.is_some() || self.cursor.is_some();
+        let Some(sort) = self.sort.or(paginate.then_some(SortField::Ip)) else {
+            return Ok(Page {
+                devices: devices.collect(),
+                next_cursor: None,
+            });
+        };
+
+        let mut keyed = devices
+            .map(|device| (Position::new(&device, sort), device))
+            .collect::<Vec<_>>();
+        keyed.sort_by(|(a, _), (b, _)| self.order.apply(a.cmp(b)));
+
+        if let Some(cursor) = &self.cursor {
+            let after = Position::decode(cursor, sort)?;
+            let start = keyed.partition_point(|(position, _)| {
+                self.order.apply(position.cmp(&after)) != Ordering::Greater
+            });
+            keyed.drain(..start);
+        }
+
+        let next_cursor = match self.limit {
+            Some(limit) if keyed.len() > limit => {
+                keyed.truncate(limit);
+                keyed
+                    .last()
+                    .map(|(position, _)| position.encode())
+                    .transpose()?
+            }
+            _ => None,
+        };
+
+        Ok(Page {
+            devices: keyed.into_iter().map(|(_, device)| device).collect(),
+            next_cursor,
+        })
+    }
+
+    fn lease_types(&self) -> Result<Vec<&'static str>, Error> {
+        split_names(
+            self.lease_type.as_deref(),
+            &LEASE_TYPES,
+            Error::UnknownLeaseType,
+        )
+    }
+
+    fn mac_kinds(&self) -> Result<Vec<&'static str>, Error> {
+        split_names(
+            self.mac_kind.as_deref(),
+            &MacKind::NAMES,
+            Error::UnknownMacKind,
+        )
+    }
+
+    fn matches(&self, device: &Device, lease_types: &[&str], mac_kinds: &[&str]) -> bool {
+        if !lease_types.is_empty() && !lease_types.contains(&device.lease().name()) {
+            return false;
+        }
+        if !mac_kinds.is_empty() && !mac_kinds.iter().any(|k| device.mac_kind().is(k)) {
+            return false;
+        }
+        if let Some(vendor) = &self.vendor {
+            if !device
+                .vendor()
+                .is_some_and(|v| v.eq_ignore_ascii_case(vendor))
+            {
+                return false;
+            }
+        }
+        if let Some(has_hostname) = self.has_hostname {
+            if device.hostname().is_some() != has_hostname {
+                return false;
+            }
+        }
+        if self.seen_after.is_some() || self.seen_before.is_some() {
+            let Some(last_seen) = device.last_seen() else {
+                return false;
+            };
+            if self.seen_after.is_some_and(|after| last_seen < after)
+                || self.seen_before.is_some_and(|before| last_seen > before)
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+fn expires_within(device: &Device, within: Duration) -> bool {
+    device.lease().name() == "active"
+        && device
+            .timing()
+            .and_then(LeaseTiming::remaining_seconds)
+            .is_some_and(|remaining| remaining <= within.num_seconds())
+}
+
+/// Split a comma separated list, checking each name against `known`.
+fn split_names<F>(
+    list: Option<&str>,
+    known: &[&'static str],
+    unknown: F,
+) -> Result<Vec<&'static str>, Error>
+where
+    F: Fn(String) -> Error,
+{
+    let Some(list) = list else {
+        return Ok(Vec::new());
+    };
+
+    list.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            known
+                .iter()
+                .find(|k| k.eq_ignore_ascii_case(name))
+                .copied()
+                .ok_or_else(|| unknown(name.to_owned()))
+        })
+        .collect()
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+enum Key {
+    Ip(Ipv4Addr),
+    Mac(MacAddr),
+    Text(Option<String>),
+    Time(LeaseTime),
+}
+
+/// Where a device sits in a sorted listing. The fields after `key` break
+/// ties so that every device has a distinct position, which is what lets a
+/// cursor resume after reloads.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct Position {
+    sort: SortField,
+    key: Key,
+    address: Ipv4Addr,
+    mac: MacAddr,
+    lease_type: String,
+    starts: LeaseTime,
+    source: String,
+}
+
+impl Position {
+    fn new(device: &Device, sort: SortField) -> Self {
+        let key = match sort {
+            SortField::Ip => Key::Ip(device.address()),
+            SortField::Mac => Key::Mac(device.hardware_ethernet().clone()),
+            SortField::Hostname => Key::Text(device.hostname().map(str::to_lowercase)),
+            SortField::Vendor => Key::Text(device.vendor().map(str::to_lowercase)),
+            SortField::LastSeen => Key::Time(device.last_seen()),
+        };
+
+        Self {
+            sort,
+            key,
+            address: device.address(),
+            mac: device.hardware_ethernet().clone(),
+            lease_type: device.lease().name().to_owned(),
+            starts: device.starts(),
+            source: device.source().to_owned(),
+        }
+    }
+
+    /// Cursors are hex encoded JSON, so they never need URL escaping.
+    fn encode(&self) -> Result<String, Error> {
+        let json = serde_json::to_vec(self).map_err(Error::Cursor)?;
+        Ok(json.iter().fold(String::new(), |mut s, b| {
+            let _ = write!(s, "{b:02x}");
+            s
+        }))
+    }
+
+    fn decode(cursor: &str, sort: SortField) -> Result<Self, Error> {
+        let json = (0..cursor.len())
+            .step_by(2)
+            .map(|i| {
+                cursor
+                    .get(i..i + 2)
+                    .and_then(|b| u8::from_str_radix(b, 16).ok())
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(Error::InvalidCursor)?;
+        let position: Self = serde_json::from_slice(&json).map_err(|_| Error::InvalidCursor)?;
+        if position.sort != sort {
+            return Err(Error::InvalidCursor);
+        }
+
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::{
+        clock::{Clock, FixedClock},
+        model::{Host, Lease},
+    };
+
+    fn now() -> DateTime<Utc> {
+        FixedClock(Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap()).now()
+    }
+
+    fn lease(d: u8, hours_ago: i64) -> Lease {
+        let starts = now() - Duration::hours(hours_ago);
+        Lease::test(d)
+            .with_times(starts, starts + Duration::hours(12))
+            .with_cltt(starts)
+    }
+
+    fn fixtures() -> (Vec<Lease>, Vec<Host>) {
+        let leases = vec![
+            lease(9, 1).with_hostname("b-phone"),
+            lease(3, 48).with_hostname("a-laptop"),
+            lease(5, 2),
+            lease(1, 3).with_hostname("C-tv"),
+        ];
+        let hosts = vec![Host {
+            label: "s_lan_0".to_string(),
+            fixed_address: Ipv4Addr::new(10, 0, 0, 2),
+            hardware_ethernet: MacAddr::from([0, 0, 0, 0, 0, 2]),
+            hostname: Some("router".to_string()),
+            source: String::new(),
+        }];
+        (leases, hosts)
+    }
+
+    fn devices<'a>(leases: &'a [Lease], hosts: &'a [Host]) -> Vec<Device<'a>> {
+        let vendor = Some("Acme");
+        leases
+            .iter()
+            .map(|l| Device::from_lease(l, vendor, now()))
+            .chain(hosts.iter().map(|h| Device::from_host(h, None)))
+            .collect()
+    }
+
+    fn addresses(page: &Page) -> Vec<u8> {
+        page.devices
+            .iter()
+            .map(|d| d.address().octets()[3])
+            .collect()
+    }
+
+    #[test]
+    fn test_filters() {
+        let (leases, hosts) = fixtures();
+
+        let query = ListQuery {
+            lease_type: Some("active".to_string()),
+            ..ListQuery::default()
+        };
+        let page = query.apply(devices(&leases, &hosts), now()).unwrap();
+        assert_eq!(addresses(&page), vec![9, 5, 1]);
+
+        let query = ListQuery {
+            lease_type: Some("expired, static".to_string()),
+            ..ListQuery::default()
+        };
+        let page = query.apply(devices(&leases, &hosts), now()).unwrap();
+        assert_eq!(addresses(&page), vec![3, 2]);
+
+        let query = ListQuery {
+            has_hostname: Some(false),
+            ..ListQuery::default()
+        };
+        let page = query.apply(devices(&leases, &hosts), now()).unwrap();
+        assert_eq!(addresses(&page), vec![5]);
+
+        let query = ListQuery {
+            vendor: Some("acme".to_string()),
+            seen_after: Some(now() - Duration::hours(4)),
+            ..ListQuery::default()
+        };
+        let page = query.apply(devices(&leases, &hosts), now()).unwrap();
+        assert_eq!(addresses(&page), vec![9, 5, 1]);
+
+        let query = ListQuery {
+            lease_type: Some("bogus".to_string()),
+            ..ListQuery::default()
+        };
+        query
+            .apply(devices(&leases, &hosts), now())
+            .expect_err("Unknown lease type");
+    }
+
+    #[test]
+    fn test_expiring_within() {
+        let (leases, hosts) = fixtures();
+
+        let query = ListQuery {
+            expiring_within: Some("10h".to_string()),
+            ..ListQuery::default()
+        };
+        let page = query.apply(devices(&leases, &hosts), now()).unwrap();
+        assert_eq!(addresses(&page), vec![5, 1]);
+
+        let query = ListQuery {
+            expiring_within: Some("soon".to_string()),
+            ..ListQuery::default()
+        };
+        query
+            .apply(devices(&leases, &hosts), now())
+            .expect_err("Invalid duration");
+    }
+
+    #[test]
+    fn test_sort() {
+        let (leases, hosts) = fixtures();
+
+        let query = ListQuery {
+            sort: Some(SortField::Hostname),
+            ..ListQuery::default()
+        };
+        let page = query.apply(devices(&leases, &hosts), now()).unwrap();
+        assert_eq!(addresses(&page), vec![5, 3, 9, 1, 2]);
+
+        let query = ListQuery {
+            sort: Some(SortField::LastSeen),
+            order: SortOrder::Desc,
+            ..ListQuery::default()
+        };
+        let page = query.apply(devices(&leases, &hosts), now()).unwrap();
+        assert_eq!(addresses(&page), vec![9, 5, 1, 3, 2]);
+    }
+
+    #[test]
+    fn test_pagination() {
+        let (leases, hosts) = fixtures();
+        let mut seen = Vec::new();
+        let mut cursor = None;
+
+        loop {
+            let query = ListQuery {
+                limit: Some(2),
+                cursor: cursor.take(),
+                ..ListQuery::default()
+            };
+            let page = query.apply(devices(&leases, &hosts), now()).unwrap();
+            seen.extend(addresses(&page));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(seen, vec![1, 2, 3, 5, 9]);
+
+        // Two leases of the same address and MAC only differ in when they
+        // started.
+        let mut earlier = lease(3, 72).with_hostname("a-laptop");
+        earlier.ends = earlier.starts;
+        let leases = [earlier, leases[1].clone()];
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let query = ListQuery {
+                limit: Some(1),
+                cursor: cursor.take(),
+                ..ListQuery::default()
+            };
+            let page = query.apply(devices(&leases, &[]), now()).unwrap();
+            seen.extend(page.devices.iter().map(Device::starts));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(seen, vec![leases[0].starts, leases[1].starts]);
+
+        let query = ListQuery {
+            cursor: Some("zz".to_string()),
+            ..ListQuery::default()
+        };
+        query
+            .apply(devices(&leases, &hosts), now())
+            .expect_err("Invalid cursor");
+    }
+}
@@ -11,6 +11,84 @@ impl MacAddr {
     pub fn bytes(&self) -> &[u8] {
         &self.0[..]
     }
+
+    /// The I/G bit: set for group (multicast and broadcast) addresses.
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0b0001 != 0
+    }
+
+    /// The U/L bit: set for locally administered addresses, which is what
+    /// phones use for private (randomized) Wi-Fi addresses.
+    pub fn is_local(&self) -> bool {
+        self.0[0] & 0b0010 != 0
+    }
+
+    pub fn kind(&self) -> MacKind {
+        let slap_quadrant = self.is_local().then(|| match (self.0[0] >> 2) & 0b11 {
+            0b00 => SlapQuadrant::Aai,
+            0b01 => SlapQuadrant::Reserved,
+            0b10 => SlapQuadrant::Eli,
+            _ => SlapQuadrant::Sai,
+        });
+
+        MacKind {
+            local: self.is_local(),
+            multicast: self.is_multicast(),
+            slap_quadrant,
+        }
+    }
+}
+
+/// IEEE 802c Structured Local Address Plan quadrant, from the Y and Z bits
+/// of a locally administered address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlapQuadrant {
+    /// Administratively Assigned Identifier (`x2`), used by random MACs.
+    Aai,
+    /// Extended Local Identifier (`xa`), derived from a CID.
+    Eli,
+    /// Standard Assigned Identifier (`xe`), assigned by a protocol.
+    Sai,
+    /// Reserved (`x6`).
+    Reserved,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MacKind {
+    pub local: bool,
+    pub multicast: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slap_quadrant: Option<SlapQuadrant>,
+}
+
+impl MacKind {
+    pub const NAMES: [&'static str; 8] = [
+        "universal",
+        "local",
+        "unicast",
+        "multicast",
+        "aai",
+        "eli",
+        "sai",
+        "reserved",
+    ];
+
+    /// Whether this kind is described by `name`, one of [`Self::NAMES`].
+    pub fn is(self, name: &str) -> bool {
+        match name {
+            "universal" => !self.local,
+            "local" => self.local,
+            "unicast" => !self.multicast,
+            "multicast" => self.multicast,
+            "aai" => self.slap_quadrant == Some(SlapQuadrant::Aai),
+            "eli" => self.slap_quadrant == Some(SlapQuadrant::Eli),
+            "sai" => self.slap_quadrant == Some(SlapQuadrant::Sai),
+            "reserved" => self.slap_quadrant == Some(SlapQuadrant::Reserved),
+            _ => false,
+        }
+    }
 }
 
 impl From<[u8; 6]> for MacAddr {
@@ -36,6 +114,9 @@ pub enum InvalidMacAddr {
 
 #[derive(Debug, thiserror::Error)]
 pub enum InvalidMacPrefix {
+    #[error("mac prefix needs at least one octet")]
+    Short,
+
     #[error("mac prefix segment contains non-hex character: {0}")]
     BadChar(char),
 
@@ -46,25 +127,52 @@ pub enum InvalidMacPrefix {
     LongSegment,
 }
 
+/// Splits a MAC address or prefix into its hex segments, returning them
+/// with the width every full segment must have. Accepts colon (`aa:bb:cc`),
+/// dash (`aa-bb-cc`), Cisco dotted (`aabb.ccdd`) and bare (`aabbcc`) forms.
+fn segments(s: &str) -> (Vec<&str>, usize) {
+    if s.contains(':') {
+        (s.split(':').collect(), 2)
+    } else if s.contains('-') {
+        (s.split('-').collect(), 2)
+    } else if s.contains('.') {
+        (s.split('.').collect(), 4)
+    } else {
+        (vec![s], 12)
+    }
+}
+
 impl FromStr for MacAddr {
     type Err = InvalidMacAddr;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut bytes = [0u8; 6];
-        let mut len = 0usize;
-        for (i, byte) in s.split(':').enumerate() {
-            if i >= bytes.len() {
+        let (segments, width) = segments(s.trim());
+        if width == 12 {
+            match segments[0].len() {
+                len if len < 12 => return Err(InvalidMacAddr::Short),
+                len if len > 12 => return Err(InvalidMacAddr::Long),
+                _ => (),
+            }
+        }
+
+        let mut hex = String::with_capacity(12);
+        for (i, segment) in segments.iter().enumerate() {
+            if i >= 12 / width {
                 return Err(InvalidMacAddr::Long);
-            } else if byte.len() != 2 {
+            } else if segment.len() != width || !segment.bytes().all(|b| b.is_ascii_hexdigit()) {
                 return Err(InvalidMacAddr::BadSegment);
             }
-            bytes[i] = u8::from_str_radix(byte, 16)?;
-            len += 1;
+            hex.push_str(segment);
         }
-        if len != bytes.len() {
+        if hex.len() != 12 {
             return Err(InvalidMacAddr::Short);
         }
 
+        let mut bytes = [0u8; 6];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+        }
+
         Ok(Self(bytes))
     }
 }
@@ -107,13 +215,22 @@ impl From<&MacAddr> for MacPrefix {
     }
 }
 
+impl MacPrefix {
+    /// Whether `mac` starts with this prefix.
+    pub fn matches(&self, mac: &MacAddr) -> bool {
+        let nibs = Nibblet::from_byte_vec(mac.bytes().to_vec());
+        (0..self.0.len()).all(|i| self.0.get(i) == nibs.get(i))
+    }
+}
+
 impl FromStr for MacPrefix {
     type Err = InvalidMacPrefix;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut nibs = Nibblet::new();
-        for byte in s.split(':') {
-            if byte.len() > 2 {
+        let (segments, width) = segments(s.trim());
+        for byte in segments {
+            if byte.len() > width {
                 return Err(InvalidMacPrefix::LongSegment);
             }
             for nib in byte.as_bytes() {
@@ -126,6 +243,9 @@ impl FromStr for MacPrefix {
                 nibs.push(nib);
             }
         }
+        if nibs.len() < 2 {
+            return Err(InvalidMacPrefix::Short);
+        }
         if nibs.len() > 12 {
             return Err(InvalidMacPrefix::Long);
         }
@@ -175,6 +295,94 @@ mod tests {
         let _ = MacAddr::from_str(mac).expect_err("Invalid MAC address");
     }
 
+    #[test]
+    fn test_notations() {
+        let expected = MacAddr::from([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+        for mac in [
+            "aa:bb:cc:dd:ee:ff",
+            "AA-BB-CC-DD-EE-FF",
+            "aabb.ccdd.eeff",
+            "AABBCCDDEEFF",
+            " aa:bb:cc:dd:ee:ff\n",
+        ] {
+            assert_eq!(MacAddr::from_str(mac).unwrap(), expected, "{mac}");
+        }
+    }
+
+    #[test]
+    fn test_bad_notations() {
+        for mac in [
+            "aa:bb:cc-dd-ee-ff",
+            "aabb.ccdd.eef",
+            "aabb.ccdd.eeff.0011",
+            "aabbccddeef",
+            "aabbccddeeff00",
+            "+a:bb:cc:dd:ee:ff",
+            "",
+        ] {
+            let _ = MacAddr::from_str(mac).expect_err(mac);
+        }
+    }
+
+    #[test]
+    fn test_prefix_notations() {
+        let mac = MacAddr::from([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+        for prefix in [
+            "aa:bb:cc",
+            "AA-BB-C",
+            "aabb.cc",
+            "aabbccd",
+            "aa:bb:cc:dd:ee:ff",
+        ] {
+            assert!(
+                MacPrefix::from_str(prefix).unwrap().matches(&mac),
+                "{prefix}"
+            );
+        }
+        assert!(!MacPrefix::from_str("aa:bc").unwrap().matches(&mac));
+
+        let e = MacPrefix::from_str("aabbc.cc").expect_err("Invalid MAC prefix");
+        assert!(matches!(e, InvalidMacPrefix::LongSegment));
+
+        // Anything shorter than an octet would match every device.
+        for prefix in ["", ":", "::", "a"] {
+            let e = MacPrefix::from_str(prefix).expect_err(prefix);
+            assert!(matches!(e, InvalidMacPrefix::Short), "{prefix}");
+        }
+    }
+
+    #[test]
+    fn test_mac_kind() {
+        let kind = |mac: &str| MacAddr::from_str(mac).unwrap().kind();
+
+        let universal = kind("f0:b3:ec:25:8c:2d");
+        assert!(!universal.local && !universal.multicast);
+        assert_eq!(universal.slap_quadrant, None);
+        assert!(universal.is("universal") && universal.is("unicast"));
+
+        let random = kind("da:a1:19:00:00:01");
+        assert!(random.local && !random.multicast);
+        assert_eq!(random.slap_quadrant, Some(SlapQuadrant::Eli));
+
+        assert_eq!(
+            kind("02:00:00:00:00:01").slap_quadrant,
+            Some(SlapQuadrant::Aai)
+        );
+        assert_eq!(
+            kind("06:00:00:00:00:01").slap_quadrant,
+            Some(SlapQuadrant::Reserved)
+        );
+        assert_eq!(
+            kind("0e:00:00:00:00:01").slap_quadrant,
+            Some(SlapQuadrant::Sai)
+        );
+        assert!(kind("0e:00:00:00:00:01").is("local"));
+
+        let broadcast = kind("ff:ff:ff:ff:ff:ff");
+        assert!(broadcast.multicast && broadcast.is("multicast"));
+        assert!(kind("01:00:5e:00:00:01").is("universal"));
+    }
+
     #[test]
     fn test_invalid_prefix() {
         let mac = "1g:20";
@@ -13,85 +13,98 @@ use tokio_util::{sync::CancellationToken, task::TaskTracker};
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
-mod args;
-mod db;
-mod dhcp_parsers;
-mod macaddr;
-mod model;
-mod vendor_macs;
-
 use std::{
     collections::BTreeSet,
+    convert::Infallible,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     str::FromStr,
     sync::Arc,
 };
 
-use args::Args;
-
 use axum::{
-    body::Body,
-    extract::{ConnectInfo, Path, State},
-    http::StatusCode,
-    response::{IntoResponse, Response},
-    routing::get,
-    Json, Router,
+    extract::{ws::WebSocketUpgrade, ConnectInfo, Path, Query, RawQuery, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{self, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use chrono::{DateTime, Utc};
+use dhcpd_api::{
+    args::Args,
+    auth::{CanWrite, WriteAccess},
+    cache::{CachedResponse, Encoding},
+    changes::{ChangeFilter, Cursor},
+    cidr::{self, Ipv4Cidr},
+    db::{self, Database, FileKind, DB},
+    events,
+    identity::Identity,
+    listing::{self, ListQuery},
+    macaddr::MacPrefix,
+    model::{self, Annotation, Lease, MacAddr},
+    query::{self, Expr},
+    render::{DefaultTimezone, Time, TimeFormat},
+    search::{Matcher, SearchQuery},
+    source::{SourceFilter, SourceNames},
+    store::{HistoryQuery, Store},
+    webhooks::{WebhookConfig, Webhooks},
+    ws::{self, AllowedOrigins},
+    Error,
 };
-use db::{Database, DB};
-use model::{Device, MacAddr};
+use futures_util::Stream;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use tokio::{net::TcpListener, sync::Mutex};
-
-use crate::model::{FindByIp, FindByMac};
-
-#[derive(Debug, thiserror::Error)]
-pub enum Error {
-    #[error("IPv6 not supported")]
-    Ipv6NotSupported,
-
-    #[error("Invalid IP address: {0}")]
-    InvalidIpAddr(#[from] std::net::AddrParseError),
-
-    #[error("Invalid MAC address: {0}")]
-    InvalidMacAddr(#[from] macaddr::InvalidMacAddr),
-
-    #[error("Database error: {0}")]
-    Database(#[from] db::Error),
-
-    #[error("Listen error: {0}")]
-    Listen(#[from] std::io::Error),
-}
+use tokio::net::TcpListener;
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> Result<(), Error> {
     let args = Args::new();
 
-    let db = Arc::new(Mutex::new(Database::new().await?));
+    let sources = args.sources()?;
+    let names = SourceNames(sources.iter().map(|source| source.name.clone()).collect());
+    let state_db = args.state_db().ok_or(Error::NoStateDb)?;
+    let store = Store::open(state_db, args.history_retention())?;
+    let webhooks = match &args.webhooks {
+        Some(path) => WebhookConfig::load(path)?,
+        None => Vec::new(),
+    };
+    let webhooks = Webhooks::new(webhooks, &names, store.clone())?;
+    let db = DB::new(Database::new(store, &sources).await?);
     let tracker = TaskTracker::new();
     let shutdown = CancellationToken::new();
 
+    if !webhooks.is_empty() {
+        let run = webhooks
+            .clone()
+            .run(db.clone(), shutdown.clone(), tracker.clone());
+        tracker.spawn(run);
+    }
+
     let files_db = db.clone();
     let files_shutdown = shutdown.clone();
     tracker.spawn(async move {
-        db::watch_files(
-            files_db,
-            files_shutdown.clone(),
-            &args.dhcpd_config,
-            &args.dhcpd_leases,
-        )
-        .await
-        .unwrap_or_else(|e| {
-            tracing::error!("watch_files error: {}", e);
-            files_shutdown.cancel();
-        });
+        db::watch_files(files_db, files_shutdown.clone(), &sources)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("watch_files error: {}", e);
+                files_shutdown.cancel();
+            });
     });
 
-    let router = Router::new()
-        .route("/", get(index))
-        .route("/whoami", get(whoami))
-        .route("/ip/:ip", get(lookup_ip))
-        .route("/mac/:mac", get(lookup_mac))
-        .route("/vendors", get(vendors))
+    let router = routes()
+        .layer(Extension(DefaultTimezone(args.timezone)))
+        .layer(Extension(names))
+        .layer(Extension(webhooks))
+        .layer(Extension(AllowedOrigins(Arc::from(
+            args.ws_origins.clone(),
+        ))))
+        .layer(Extension(WriteAccess {
+            token: args.write_token.as_deref().map(Arc::from),
+            loopback: args.allow_loopback_writes,
+        }))
+        .layer(Extension(shutdown.clone()))
         .with_state(db);
 
     let listener = TcpListener::bind(args.listen)
@@ -129,104 +142,757 @@ async fn main() -> Result<(), Error> {
     Ok(())
 }
 
-async fn index(State(db): State<DB>) -> Json<Value> {
-    let db = db.lock().await;
-    let mut devices = Vec::with_capacity(db.leases.len() + db.hosts.len());
+/// Every endpoint, before the extensions they need are added.
+fn routes() -> Router<DB> {
+    Router::new()
+        .route("/", get(index))
+        .route("/status", get(status))
+        .route("/whoami", get(whoami))
+        .route("/ip/:ip", get(lookup_ip))
+        .route("/mac/:mac", get(lookup_mac))
+        .route("/subnet/:ip/:prefix", get(lookup_subnet))
+        .route("/range/:start/:end", get(lookup_range))
+        .route("/devices", get(devices))
+        .route("/devices/:mac", get(lookup_device))
+        .route("/identities", get(identities))
+        .route("/identities/:id", get(lookup_identity))
+        .route("/history/mac/:mac", get(mac_history))
+        .route("/history/ip/:ip", get(ip_history))
+        .route("/new-devices", get(new_devices))
+        .route("/changes", get(changes))
+        .route("/events", get(events))
+        .route("/ws", get(ws))
+        .route("/webhooks/deliveries", get(webhook_deliveries))
+        .route(
+            "/webhooks/dead-letters/:id/redeliver",
+            post(redeliver_dead_letter),
+        )
+        .route(
+            "/annotations",
+            get(export_annotations).post(import_annotations),
+        )
+        .route(
+            "/annotations/:mac",
+            get(lookup_annotation)
+                .put(save_annotation)
+                .delete(delete_annotation),
+        )
+        .route("/search", get(search))
+        .route("/vendors", get(vendors))
+}
+
+/// `?at=` asks for the network as it was at some past time.
+#[derive(Debug, Deserialize)]
+struct AtQuery {
+    /// A time in the query language's format, such as `-2h` or an RFC 3339
+    /// timestamp.
+    at: Option<String>,
+}
+
+impl AtQuery {
+    /// The resolved time and the recorded leases that were active then, or
+    /// `None` for the current state.
+    async fn history(&self, db: &DB) -> Result<Option<(DateTime<Utc>, Vec<Lease>)>, Error> {
+        let Some(at) = &self.at else {
+            return Ok(None);
+        };
+        let (store, now) = {
+            let db = db.load();
+            (db.store.clone(), db.now())
+        };
+        let at = query::parse_time(at)
+            .map_err(Error::InvalidTime)?
+            .resolve(now)
+            .ok_or_else(|| Error::InvalidTime(format!("{at:?} is out of range")))?;
+        let leases = store.leases_at(at).await?;
 
-    for lease in &db.leases {
-        devices.push(Device::from_lease(lease, &db.vendor_mapping));
+        Ok(Some((at, leases)))
     }
+}
 
-    for host in &db.hosts {
-        devices.push(Device::from_host(host, &db.vendor_mapping));
+async fn index(
+    State(db): State<DB>,
+    Query(query): Query<ListQuery>,
+    Query(at): Query<AtQuery>,
+    RawQuery(raw): RawQuery,
+    filter: SourceFilter,
+    format: TimeFormat,
+    encoding: Encoding,
+) -> Result<Response, Error> {
+    if let Some((at, leases)) = at.history(&db).await? {
+        let db = db.load();
+        let page = query.apply(filter.retain(db.devices_at(&leases, at)), at)?;
+        return Ok(Json(index_json(&db, page, &filter, format)).into_response());
     }
 
-    Json(json!({
-        "devices": devices,
+    let db = db.load();
+    let key = format!("/?{}", raw.unwrap_or_default());
+    let body = db.responses.get_or_insert(key, db.now(), || {
+        let page = query.apply(filter.retain(db.devices()), db.now())?;
+        let body = serde_json::to_vec(&index_json(&db, page, &filter, format))?;
+        Ok::<_, Error>(body)
+    })?;
+
+    Ok(CachedResponse::new(&body, encoding).await.into_response())
+}
+
+/// The body of `/`.
+fn index_json(
+    db: &Database,
+    page: listing::Page,
+    filter: &SourceFilter,
+    format: TimeFormat,
+) -> Value {
+    json!({
+        "devices": format.apply(page.devices),
+        "next_cursor": page.next_cursor,
         "last_update": {
-            "leases": db.last_update_leases,
-            "hosts": db.last_update_hosts,
-            "check": db.last_update_check,
+            "leases": format.apply(Time::from(db.last_success(FileKind::Leases, filter))),
+            "hosts": format.apply(Time::from(db.last_success(FileKind::Hosts, filter))),
+            "check": format.apply(Time::from(db.last_update_check)),
+            "watch_mode": db.watch_mode,
+            "reload": reload_status(db, filter, format),
         }
+    })
+}
+
+async fn status(State(db): State<DB>, filter: SourceFilter, format: TimeFormat) -> Json<Value> {
+    let db = db.load();
+    let leases = db.leases.iter().filter(|l| filter.matches(&l.source));
+    let hosts = db.hosts.iter().filter(|h| filter.matches(&h.source));
+
+    Json(json!({
+        "watch_mode": db.watch_mode,
+        "generation": db.generation,
+        "leases": leases.count(),
+        "hosts": hosts.count(),
+        "last_update": {
+            "leases": format.apply(Time::from(db.last_success(FileKind::Leases, &filter))),
+            "hosts": format.apply(Time::from(db.last_success(FileKind::Hosts, &filter))),
+            "check": format.apply(Time::from(db.last_update_check)),
+        },
+        "reload": reload_status(&db, &filter, format),
     }))
 }
 
+/// Reload status by source name, for the sources `filter` allows.
+fn reload_status(db: &Database, filter: &SourceFilter, format: TimeFormat) -> Value {
+    let sources = db
+        .sources
+        .iter()
+        .filter(|source| filter.matches(&source.name))
+        .map(|source| {
+            let status = json!({
+                "leases": format.apply(source.leases.clone()),
+                "hosts": format.apply(source.hosts.clone()),
+            });
+            (source.name.clone(), status)
+        })
+        .collect::<serde_json::Map<_, _>>();
+
+    Value::Object(sources)
+}
+
 async fn whoami(
     State(db): State<DB>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    filter: SourceFilter,
+    format: TimeFormat,
 ) -> Result<Json<Value>, Error> {
     let client_ip = match addr.ip() {
         IpAddr::V4(client_ip) => Ok(client_ip),
         IpAddr::V6(_) => Err(Error::Ipv6NotSupported),
     }?;
-    let db = db.lock().await;
-
-    let leases = db.leases.find_by_ip(client_ip);
-    let hosts = db.hosts.find_by_ip(client_ip);
+    let db = db.load();
 
     Ok(Json(json!({
-        "devices": Device::from_leases_and_hosts(&leases, &hosts, &db.vendor_mapping),
+        "devices": format.apply(filter.retain(db.find_by_ip(client_ip))),
     })))
 }
 
-async fn lookup_ip(State(db): State<DB>, Path(ip): Path<String>) -> Result<Json<Value>, Error> {
+async fn lookup_ip(
+    State(db): State<DB>,
+    Path(ip): Path<String>,
+    Query(at): Query<AtQuery>,
+    filter: SourceFilter,
+    format: TimeFormat,
+) -> Result<Json<Value>, Error> {
     let ip = Ipv4Addr::from_str(&ip)?;
-    let db = db.lock().await;
+    let history = at.history(&db).await?;
+    let db = db.load();
+    let devices = match &history {
+        Some((at, leases)) => db
+            .devices_at(leases, *at)
+            .into_iter()
+            .filter(|device| device.address() == ip)
+            .collect(),
+        None => db.find_by_ip(ip),
+    };
+    let devices = filter.retain(devices);
+
+    Ok(Json(json!({
+        "devices": format.apply(devices),
+    })))
+}
+
+/// A full MAC address, or a prefix of one such as an OUI.
+enum MacQuery {
+    Full(MacAddr),
+    Prefix(MacPrefix),
+}
+
+impl MacQuery {
+    fn parse(s: &str) -> Result<Self, Error> {
+        match s.parse::<MacAddr>() {
+            Ok(mac) => Ok(Self::Full(mac)),
+            Err(e) => Ok(Self::Prefix(s.parse::<MacPrefix>().map_err(|_| e)?)),
+        }
+    }
+
+    fn matches(&self, mac: &MacAddr) -> bool {
+        match self {
+            Self::Full(full) => full == mac,
+            Self::Prefix(prefix) => prefix.matches(mac),
+        }
+    }
+}
 
-    let leases = db.leases.find_by_ip(ip);
-    let hosts = db.hosts.find_by_ip(ip);
+/// Accepts a full MAC address in any common notation, or a prefix such as
+/// an OUI (`aa:bb:cc`) to list every device under it.
+async fn lookup_mac(
+    State(db): State<DB>,
+    Path(mac): Path<String>,
+    Query(at): Query<AtQuery>,
+    filter: SourceFilter,
+    format: TimeFormat,
+) -> Result<Json<Value>, Error> {
+    let mac = MacQuery::parse(&mac)?;
+    let history = at.history(&db).await?;
+    let db = db.load();
+
+    let devices = match (&history, &mac) {
+        (Some((at, leases)), _) => db
+            .devices_at(leases, *at)
+            .into_iter()
+            .filter(|device| mac.matches(device.hardware_ethernet()))
+            .collect(),
+        (None, MacQuery::Full(mac)) => db.find_by_mac(mac),
+        (None, MacQuery::Prefix(prefix)) => db.find_by_mac_prefix(prefix),
+    };
+    let devices = filter.retain(devices);
 
     Ok(Json(json!({
-        "devices": Device::from_leases_and_hosts(&leases, &hosts, &db.vendor_mapping),
+        "devices": format.apply(devices),
     })))
 }
 
-async fn lookup_mac(State(db): State<DB>, Path(mac): Path<String>) -> Result<Json<Value>, Error> {
-    let db = db.lock().await;
-    let mac = mac.parse::<MacAddr>()?;
+#[derive(Debug, Deserialize)]
+struct RangeQuery {
+    #[serde(default)]
+    gaps: bool,
+}
+
+async fn lookup_subnet(
+    State(db): State<DB>,
+    Path((ip, prefix)): Path<(String, String)>,
+    Query(query): Query<RangeQuery>,
+    Query(list): Query<ListQuery>,
+    filter: SourceFilter,
+    format: TimeFormat,
+) -> Result<Json<Value>, Error> {
+    let cidr = Ipv4Cidr::from_str(&format!("{ip}/{prefix}"))?;
+    let db = db.load();
+
+    let (start, end) = (cidr.first(), cidr.last());
+    range_response(&db, start, end, &query, &list, &filter, format).map(Json)
+}
+
+async fn lookup_range(
+    State(db): State<DB>,
+    Path((start, end)): Path<(String, String)>,
+    Query(query): Query<RangeQuery>,
+    Query(list): Query<ListQuery>,
+    filter: SourceFilter,
+    format: TimeFormat,
+) -> Result<Json<Value>, Error> {
+    let start = Ipv4Addr::from_str(&start)?;
+    let end = Ipv4Addr::from_str(&end)?;
+    if start > end {
+        return Err(Error::InvalidRange(start, end));
+    }
+    let db = db.load();
+
+    range_response(&db, start, end, &query, &list, &filter, format).map(Json)
+}
+
+fn range_response(
+    db: &Database,
+    start: Ipv4Addr,
+    end: Ipv4Addr,
+    query: &RangeQuery,
+    list: &ListQuery,
+    filter: &SourceFilter,
+    format: TimeFormat,
+) -> Result<Value, Error> {
+    let devices = filter.retain(db.find_in_range(start, end));
+    let used = devices
+        .iter()
+        .map(model::Device::address)
+        .collect::<BTreeSet<_>>();
+    let page = list.apply(devices, db.now())?;
+    if !query.gaps {
+        return Ok(json!(format.apply(page)));
+    }
 
-    let leases = db.leases.find_by_mac(&mac);
-    let hosts = db.hosts.find_by_mac(&mac);
+    Ok(json!({
+        "devices": format.apply(page.devices),
+        "next_cursor": page.next_cursor,
+        "gaps": cidr::gaps(start, end, used),
+    }))
+}
+
+/// `?q=` on `/devices` keeps the devices with a lease or static mapping
+/// that matches the expression.
+#[derive(Debug, Deserialize)]
+struct DevicesQuery {
+    q: Option<String>,
+}
+
+async fn devices(
+    State(db): State<DB>,
+    Query(query): Query<DevicesQuery>,
+    filter: SourceFilter,
+    format: TimeFormat,
+) -> Result<Json<Value>, Error> {
+    let db = db.load();
+    let mut devices = db.merged_devices(&filter);
+    if let Some(q) = &query.q {
+        let expr = Expr::from_str(q).map_err(listing::Error::from)?;
+        let now = db.now();
+        expr.check_times(now).map_err(Error::InvalidTime)?;
+        let macs = filter
+            .retain(db.devices())
+            .iter()
+            .filter(|device| expr.matches(device, now))
+            .map(model::Device::hardware_ethernet)
+            .collect::<BTreeSet<_>>();
+        devices.retain(|device| macs.contains(device.hardware_ethernet()));
+    }
+
+    Ok(Json(json!({
+        "devices": format.apply(devices),
+    })))
+}
+
+async fn lookup_device(
+    State(db): State<DB>,
+    Path(mac): Path<String>,
+    filter: SourceFilter,
+    format: TimeFormat,
+) -> Result<Json<Value>, Error> {
+    let db = db.load();
+    let mac = mac.parse::<MacAddr>()?;
+    let device = db.merged_device(&mac, &filter).ok_or(Error::NotFound)?;
 
     Ok(Json(json!({
-        "devices": Device::from_leases_and_hosts(&leases, &hosts, &db.vendor_mapping),
+        "device": format.apply(device),
     })))
 }
 
-async fn vendors(State(db): State<DB>) -> Result<Json<Value>, Error> {
-    let db = db.lock().await;
-    let mut vendors = BTreeSet::new();
+#[derive(Debug, Deserialize)]
+struct IdentityQuery {
+    mac: Option<String>,
+}
 
-    for lease in &db.leases {
-        if let Some(vendor) = db.vendor_mapping.get_vendor_name(&lease.hardware_ethernet) {
-            vendors.insert(vendor);
+async fn identities(
+    State(db): State<DB>,
+    Query(query): Query<IdentityQuery>,
+    filter: SourceFilter,
+    format: TimeFormat,
+) -> Result<Json<Value>, Error> {
+    let db = db.load();
+    let identities = match query.mac {
+        Some(mac) => {
+            let mac = mac.parse::<MacAddr>()?;
+            db.identities.by_mac(&mac).into_iter().collect()
         }
+        None => db.identities.all().iter().collect::<Vec<_>>(),
+    };
+    let identities = identities
+        .into_iter()
+        .filter(|identity| in_source(&db, identity, &filter))
+        .collect::<Vec<_>>();
+
+    Ok(Json(json!({
+        "identities": format.apply(identities.into_iter().cloned().collect::<Vec<_>>()),
+    })))
+}
+
+async fn lookup_identity(
+    State(db): State<DB>,
+    Path(id): Path<String>,
+    filter: SourceFilter,
+    format: TimeFormat,
+) -> Result<Json<Value>, Error> {
+    let db = db.load();
+    let identity = db
+        .identities
+        .by_id(&id)
+        .filter(|identity| in_source(&db, identity, &filter))
+        .ok_or(Error::NotFound)?;
+
+    Ok(Json(json!({
+        "identity": format.apply(identity.clone()),
+    })))
+}
+
+/// Whether any of the identity's leases came from a source `filter` allows.
+fn in_source(db: &Database, identity: &Identity, filter: &SourceFilter) -> bool {
+    identity
+        .leases
+        .iter()
+        .any(|&i| db.leases.get(i).is_some_and(|l| filter.matches(&l.source)))
+}
+
+async fn mac_history(
+    State(db): State<DB>,
+    Path(mac): Path<String>,
+    Query(mut query): Query<HistoryQuery>,
+    filter: SourceFilter,
+    format: TimeFormat,
+) -> Result<Json<Value>, Error> {
+    let mac = mac.parse::<MacAddr>()?;
+    query.source = filter.name().map(str::to_owned);
+    let store = db.load().store.clone();
+    let history = store.mac_history(&mac, query).await?;
+
+    Ok(Json(json!({
+        "events": format.apply(history.events),
+        "next_before": history.next_before,
+    })))
+}
+
+async fn ip_history(
+    State(db): State<DB>,
+    Path(ip): Path<String>,
+    Query(mut query): Query<HistoryQuery>,
+    filter: SourceFilter,
+    format: TimeFormat,
+) -> Result<Json<Value>, Error> {
+    let ip = Ipv4Addr::from_str(&ip)?;
+    query.source = filter.name().map(str::to_owned);
+    let store = db.load().store.clone();
+    let history = store.ip_history(ip, query).await?;
+
+    Ok(Json(json!({
+        "events": format.apply(history.events),
+        "next_before": history.next_before,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct NewDevicesQuery {
+    /// A time in the query language's format, such as `-1d` or a date.
+    since: String,
+}
+
+async fn new_devices(
+    State(db): State<DB>,
+    Query(query): Query<NewDevicesQuery>,
+    filter: SourceFilter,
+    format: TimeFormat,
+) -> Result<Json<Value>, Error> {
+    let db = db.load();
+    let since = query::parse_time(&query.since)
+        .map_err(Error::InvalidTime)?
+        .resolve(db.now())
+        .ok_or_else(|| Error::InvalidTime(format!("{:?} is out of range", query.since)))?;
+    let devices = db
+        .new_devices(since, &filter)
+        .into_iter()
+        .map(|(mac, vendor, sighting)| {
+            json!({
+                "hardware_ethernet": mac,
+                "vendor": vendor,
+                "sighting": format.apply(sighting.clone()),
+            })
+        });
+
+    Ok(Json(json!({
+        "devices": devices.collect::<Vec<_>>(),
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct ChangesQuery {
+    /// The last sequence number the client has seen.
+    #[serde(default)]
+    since: u64,
+
+    /// The `epoch` of the response `since` came from.
+    epoch: Option<String>,
+}
+
+async fn changes(
+    State(db): State<DB>,
+    Query(query): Query<ChangesQuery>,
+    filter: SourceFilter,
+    format: TimeFormat,
+) -> Json<Value> {
+    let db = db.load();
+    let journal = &db.journal;
+    let since = Cursor {
+        epoch: query.epoch,
+        seq: query.since,
+    };
+    let changes = journal
+        .since(&since)
+        .filter(|change| filter.matches(&change.source))
+        .collect::<Vec<_>>();
+
+    Json(json!({
+        "changes": format.apply(changes.into_iter().cloned().collect::<Vec<_>>()),
+        "epoch": journal.epoch(),
+        "last_seq": journal.last_seq(),
+        "missed": journal.missed(&since),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    subnet: Option<String>,
+    vendor: Option<String>,
+    /// One or more MACs, separated by commas.
+    mac: Option<String>,
+}
+
+impl EventsQuery {
+    fn filter(self, source: SourceFilter) -> Result<ChangeFilter, Error> {
+        let subnet = match self.subnet.filter(|s| !s.is_empty()) {
+            Some(subnet) => Some(subnet.parse()?),
+            None => None,
+        };
+        let macs = self
+            .mac
+            .iter()
+            .flat_map(|macs| macs.split(','))
+            .filter(|mac| !mac.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+
+        Ok(ChangeFilter {
+            source,
+            subnet,
+            vendor: self.vendor.filter(|v| !v.is_empty()),
+            macs,
+            actions: Vec::new(),
+        })
     }
-    for host in &db.hosts {
-        if let Some(vendor) = db.vendor_mapping.get_vendor_name(&host.hardware_ethernet) {
-            vendors.insert(vendor);
-        }
+}
+
+/// Device changes as Server-Sent Events. Browsers resume with the
+/// `Last-Event-ID` header after reconnecting.
+async fn events(
+    State(db): State<DB>,
+    Extension(shutdown): Extension<CancellationToken>,
+    Query(query): Query<EventsQuery>,
+    headers: HeaderMap,
+    source: SourceFilter,
+    format: TimeFormat,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, Infallible>>>, Error> {
+    let since = headers
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.trim().parse::<Cursor>().ok());
+    let stream = events::stream(db, since, query.filter(source)?, format, shutdown);
+
+    Ok(Sse::new(stream).keep_alive(events::keep_alive()))
+}
+
+/// Subscriptions and lookups over a WebSocket. See [`ws::serve`]. Pages
+/// from other sites can only connect if `--ws-origin` allows them.
+async fn ws(
+    State(db): State<DB>,
+    Extension(shutdown): Extension<CancellationToken>,
+    Extension(names): Extension<SourceNames>,
+    Extension(origins): Extension<AllowedOrigins>,
+    headers: HeaderMap,
+    format: TimeFormat,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, Error> {
+    if !origins.allows(&headers) {
+        return Err(Error::ForbiddenOrigin);
     }
+    let session = ws::Session::new(db, names, format);
+
+    Ok(upgrade.on_upgrade(move |socket| ws::serve(socket, session, shutdown)))
+}
+
+/// How many dead letters `/webhooks/deliveries` shows.
+const DEAD_LETTERS_SHOWN: usize = 100;
+
+async fn webhook_deliveries(
+    Extension(webhooks): Extension<Webhooks>,
+    format: TimeFormat,
+) -> Result<Json<Value>, Error> {
+    let dead_letters = webhooks.dead_letters(DEAD_LETTERS_SHOWN).await?;
 
     Ok(Json(json!({
-        "vendors": vendors,
+        "deliveries": format.apply(webhooks.deliveries()),
+        "dead_letters": format.apply(dead_letters),
     })))
 }
 
-impl IntoResponse for Error {
-    fn into_response(self) -> Response<Body> {
-        let resp = match self {
-            Error::Ipv6NotSupported => (StatusCode::BAD_REQUEST, "IPv6 not supported".to_string()),
-            Error::InvalidIpAddr(e) => (StatusCode::BAD_REQUEST, e.to_string()),
-            Error::InvalidMacAddr(e) => (StatusCode::BAD_REQUEST, e.to_string()),
-            _ => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Internal server error".to_string(),
-            ),
-        };
+async fn redeliver_dead_letter(
+    State(db): State<DB>,
+    _: CanWrite,
+    Extension(webhooks): Extension<Webhooks>,
+    Path(id): Path<i64>,
+    format: TimeFormat,
+) -> Result<Json<Value>, Error> {
+    let delivery = webhooks
+        .redeliver(id, db.load().now())
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    Ok(Json(json!({ "delivery": format.apply(delivery) })))
+}
+
+/// One annotation in the export format.
+#[derive(Debug, Serialize, Deserialize)]
+struct AnnotatedMac {
+    hardware_ethernet: MacAddr,
+
+    #[serde(flatten)]
+    annotation: Annotation,
+}
+
+async fn export_annotations(State(db): State<DB>) -> Json<Value> {
+    let db = db.load();
+    let annotations = db
+        .annotations
+        .iter()
+        .map(|(mac, annotation)| AnnotatedMac {
+            hardware_ethernet: mac.clone(),
+            annotation: annotation.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    Json(json!(annotations))
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportQuery {
+    /// Delete every annotation that isn't in the import.
+    #[serde(default)]
+    replace: bool,
+}
+
+async fn import_annotations(
+    State(db): State<DB>,
+    _: CanWrite,
+    Query(query): Query<ImportQuery>,
+    Json(annotations): Json<Vec<AnnotatedMac>>,
+) -> Result<Json<Value>, Error> {
+    if query.replace && annotations.is_empty() {
+        return Err(Error::EmptyReplace);
+    }
+    let count = annotations.len();
+    let annotations = annotations
+        .into_iter()
+        .map(|a| (a.hardware_ethernet, a.annotation))
+        .collect();
+    let store = db.load().store.clone();
+    store.save_annotations(annotations, query.replace).await?;
+    db.reload_annotations().await?;
+
+    Ok(Json(json!({
+        "imported": count,
+    })))
+}
+
+async fn lookup_annotation(
+    State(db): State<DB>,
+    Path(mac): Path<String>,
+) -> Result<Json<Value>, Error> {
+    let mac = mac.parse::<MacAddr>()?;
+    let db = db.load();
+    let annotation = db.annotations.get(&mac).ok_or(Error::NotFound)?;
+
+    Ok(Json(json!({
+        "annotation": annotation,
+    })))
+}
 
-        resp.into_response()
+async fn save_annotation(
+    State(db): State<DB>,
+    _: CanWrite,
+    Path(mac): Path<String>,
+    Json(annotation): Json<Annotation>,
+) -> Result<Json<Value>, Error> {
+    let mac = mac.parse::<MacAddr>()?;
+    let store = db.load().store.clone();
+    store
+        .save_annotations(vec![(mac, annotation.clone())], false)
+        .await?;
+    db.reload_annotations().await?;
+
+    Ok(Json(json!({
+        "annotation": annotation,
+    })))
+}
+
+async fn delete_annotation(
+    State(db): State<DB>,
+    _: CanWrite,
+    Path(mac): Path<String>,
+) -> Result<StatusCode, Error> {
+    let mac = mac.parse::<MacAddr>()?;
+    let store = db.load().store.clone();
+    if !store.delete_annotation(&mac).await? {
+        return Err(Error::NotFound);
     }
+    db.reload_annotations().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn search(
+    State(db): State<DB>,
+    Query(query): Query<SearchQuery>,
+    filter: SourceFilter,
+    format: TimeFormat,
+) -> Result<Json<Value>, Error> {
+    let matcher = Matcher::new(&query.q, query.mode)?;
+    let db = db.load();
+
+    Ok(Json(json!({
+        "devices": format.apply(filter.retain(db.search(&matcher))),
+    })))
+}
+
+async fn vendors(
+    State(db): State<DB>,
+    RawQuery(raw): RawQuery,
+    filter: SourceFilter,
+    encoding: Encoding,
+) -> Result<CachedResponse, Error> {
+    let db = db.load();
+    let key = format!("/vendors?{}", raw.unwrap_or_default());
+    let body = db.responses.get_or_insert(key, db.now(), || {
+        let vendors = if filter.is_all() {
+            db.index.vendors().collect::<Vec<_>>()
+        } else {
+            let devices = filter.retain(db.devices());
+            let vendors = devices.iter().filter_map(model::Device::vendor);
+            vendors.collect::<BTreeSet<_>>().into_iter().collect()
+        };
+        serde_json::to_vec(&json!({
+            "vendors": vendors,
+        }))
+    })?;
+
+    Ok(CachedResponse::new(&body, encoding).await)
 }
 
 async fn shutdown_signal() {
@@ -254,3 +920,197 @@ async fn shutdown_signal() {
         () = terminate => {},
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use axum::extract::FromRequestParts;
+    use dhcpd_api::dhcp_parsers;
+
+    use super::*;
+
+    /// Far enough back that no date can be that far before now.
+    const OUT_OF_RANGE: &str = "-100000000d";
+
+    fn database() -> DB {
+        DB::new(Database::in_memory(&["lan"]))
+    }
+
+    fn status(result: Result<impl IntoResponse, Error>) -> StatusCode {
+        match result {
+            Ok(response) => response.into_response().status(),
+            Err(e) => e.into_response().status(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_out_of_range_times() {
+        let db = database();
+
+        let query = NewDevicesQuery {
+            since: OUT_OF_RANGE.to_owned(),
+        };
+        let response = new_devices(
+            State(db.clone()),
+            Query(query),
+            SourceFilter::default(),
+            TimeFormat::default(),
+        )
+        .await;
+        assert!(matches!(response, Err(Error::InvalidTime(_))));
+        assert_eq!(status(response), StatusCode::BAD_REQUEST);
+
+        let index = |list: ListQuery, at: Option<&str>| {
+            let at = AtQuery {
+                at: at.map(str::to_owned),
+            };
+            index(
+                State(db.clone()),
+                Query(list),
+                Query(at),
+                RawQuery(None),
+                SourceFilter::default(),
+                TimeFormat::default(),
+                Encoding::Identity,
+            )
+        };
+        let response = index(ListQuery::default(), Some(OUT_OF_RANGE)).await;
+        assert_eq!(status(response), StatusCode::BAD_REQUEST);
+
+        let list = ListQuery {
+            q: Some(format!("last_seen>{OUT_OF_RANGE}")),
+            ..ListQuery::default()
+        };
+        let response = index(list, None).await;
+        assert_eq!(status(response), StatusCode::BAD_REQUEST);
+
+        assert_eq!(
+            status(index(ListQuery::default(), Some("-1d")).await),
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_annotations() {
+        let db = database();
+        let mac = "02:00:00:00:00:01";
+        let annotation = serde_json::from_value::<Annotation>(json!({ "name": "TV" })).unwrap();
+        let saved = save_annotation(
+            State(db.clone()),
+            CanWrite,
+            Path(mac.to_owned()),
+            Json(annotation),
+        )
+        .await;
+        assert_eq!(status(saved), StatusCode::OK);
+        assert_eq!(db.load().annotations.len(), 1);
+
+        let replace = import_annotations(
+            State(db.clone()),
+            CanWrite,
+            Query(ImportQuery { replace: true }),
+            Json(Vec::new()),
+        )
+        .await;
+        assert_eq!(status(replace), StatusCode::BAD_REQUEST);
+        assert_eq!(db.load().annotations.len(), 1);
+
+        let deleted = delete_annotation(State(db.clone()), CanWrite, Path(mac.to_owned())).await;
+        assert_eq!(status(deleted), StatusCode::NO_CONTENT);
+        assert!(db.load().annotations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_devices_query() {
+        let db = database();
+        let leases = dhcp_parsers::leases::parse(
+            "lease 10.0.0.1 {
+  starts 1 2024/01/01 10:00:00;
+  ends 1 2024/01/02 10:00:00;
+  hardware ethernet 02:00:00:00:00:01;
+  client-hostname \"laptop\";
+}
+lease 10.0.0.2 {
+  starts 1 2024/01/01 10:00:00;
+  ends 1 2024/01/02 10:00:00;
+  hardware ethernet 02:00:00:00:00:02;
+  client-hostname \"phone\";
+}
+",
+        )
+        .unwrap();
+        db::update_leases(&db, "lan", leases).await.unwrap();
+
+        let devices = |q: &str| {
+            let query = DevicesQuery {
+                q: Some(q.to_owned()),
+            };
+            devices(
+                State(db.clone()),
+                Query(query),
+                SourceFilter::default(),
+                TimeFormat::default(),
+            )
+        };
+        let Json(body) = devices("hostname:lap*").await.unwrap();
+        let macs = body["devices"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|device| device["hardware_ethernet"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(macs, ["02:00:00:00:00:01"]);
+
+        assert_eq!(status(devices("hostname:").await), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_time_format() {
+        let db = database();
+        let leases = dhcp_parsers::leases::parse(
+            "lease 10.0.0.1 {
+  starts 1 2024/01/01 10:00:00;
+  ends 1 2024/01/02 10:00:00;
+  hardware ethernet 02:00:00:00:00:01;
+}
+",
+        )
+        .unwrap();
+        db::update_leases(&db, "lan", leases).await.unwrap();
+
+        let index = |query: &'static str| {
+            let db = db.clone();
+            async move {
+                let request = axum::http::Request::get(format!("/?{query}"))
+                    .body(())
+                    .unwrap();
+                let (mut parts, ()) = request.into_parts();
+                let format = TimeFormat::from_request_parts(&mut parts, &())
+                    .await
+                    .unwrap();
+                let response = index(
+                    State(db.clone()),
+                    Query(ListQuery::default()),
+                    Query(AtQuery { at: None }),
+                    RawQuery(Some(query.to_owned())),
+                    SourceFilter::default(),
+                    format,
+                    Encoding::Identity,
+                )
+                .await
+                .unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let body: Value = serde_json::from_slice(&body).unwrap();
+                body["devices"][0]["renewal_at"].clone()
+            }
+        };
+
+        assert_eq!(index("").await, json!("2024-01-01T22:00:00Z"));
+        assert_eq!(
+            index("tz=Europe/Amsterdam&epoch=true").await,
+            json!({ "time": "2024-01-01T23:00:00+01:00", "epoch": 1_704_146_400 })
+        );
+    }
+}
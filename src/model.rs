@@ -1,10 +1,10 @@
-use std::net::Ipv4Addr;
+use std::{cmp::Reverse, net::Ipv4Addr};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-pub use crate::macaddr::MacAddr;
-use crate::vendor_macs::VendorMapping;
+pub use crate::macaddr::{MacAddr, MacKind};
+use crate::render::{Render, Time, TimeFormat};
 
 pub type LeaseTime = Option<DateTime<Utc>>;
 
@@ -17,155 +17,618 @@ pub struct Lease {
     pub cltt: LeaseTime,
     pub hardware_ethernet: MacAddr,
     pub client_hostname: Option<String>,
+    /// Raw client identifier (DHCP option 61).
+    pub uid: Option<Vec<u8>>,
+    pub vendor_class_identifier: Option<String>,
+    /// Name of the dhcpd instance the lease came from.
+    #[serde(default)]
+    pub source: String,
 }
 
 impl Lease {
-    pub fn is_expired(&self) -> bool {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
         if let Some(ends) = self.ends {
-            ends < Utc::now()
+            ends < now
         } else {
             false
         }
     }
 }
 
+/// Leases for tests, built up from [`Lease::test`].
+#[cfg(test)]
+impl Lease {
+    /// A lease of 10.0.0.`d` to 00:00:00:00:00:`d` from `lan`, with no times
+    /// or names.
+    pub fn test(d: u8) -> Self {
+        Self {
+            address: Ipv4Addr::new(10, 0, 0, d),
+            starts: None,
+            ends: None,
+            tstp: None,
+            cltt: None,
+            hardware_ethernet: MacAddr::from([0, 0, 0, 0, 0, d]),
+            client_hostname: None,
+            uid: None,
+            vendor_class_identifier: None,
+            source: "lan".to_owned(),
+        }
+    }
+
+    pub fn with_address(mut self, address: [u8; 4]) -> Self {
+        self.address = Ipv4Addr::from(address);
+        self
+    }
+
+    pub fn with_mac(mut self, mac: [u8; 6]) -> Self {
+        self.hardware_ethernet = MacAddr::from(mac);
+        self
+    }
+
+    pub fn with_hostname(mut self, hostname: &str) -> Self {
+        self.client_hostname = Some(hostname.to_owned());
+        self
+    }
+
+    pub fn with_times(mut self, starts: DateTime<Utc>, ends: DateTime<Utc>) -> Self {
+        self.starts = Some(starts);
+        self.ends = Some(ends);
+        self
+    }
+
+    /// Started an hour before `now` and ending an hour after.
+    pub fn active_at(self, now: DateTime<Utc>) -> Self {
+        let hour = chrono::Duration::hours(1);
+        self.with_times(now - hour, now + hour)
+    }
+
+    pub fn with_cltt(mut self, cltt: DateTime<Utc>) -> Self {
+        self.cltt = Some(cltt);
+        self
+    }
+
+    pub fn with_uid(mut self, uid: &[u8]) -> Self {
+        self.uid = Some(uid.to_vec());
+        self
+    }
+
+    pub fn with_vendor_class(mut self, vci: &str) -> Self {
+        self.vendor_class_identifier = Some(vci.to_owned());
+        self
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Host {
+    pub label: String,
     pub fixed_address: Ipv4Addr,
     pub hardware_ethernet: MacAddr,
     pub hostname: Option<String>,
+    /// Name of the dhcpd instance the mapping came from.
+    #[serde(default)]
+    pub source: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
 #[serde(tag = "type")]
 pub enum LeaseType {
     #[serde(rename = "active")]
-    Active { since: LeaseTime, until: LeaseTime },
+    Active { since: Time, until: Time },
     #[serde(rename = "expired")]
-    Expired { since: LeaseTime },
+    Expired { since: Time },
     #[serde(rename = "static")]
     Static,
 }
 
+impl Render for LeaseType {
+    fn render(&mut self, format: TimeFormat) {
+        match self {
+            Self::Active { since, until } => {
+                since.render(format);
+                until.render(format);
+            }
+            Self::Expired { since } => since.render(format),
+            Self::Static => {}
+        }
+    }
+}
+
+impl LeaseType {
+    /// The name used for the `type` tag when serialized.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Active { .. } => "active",
+            Self::Expired { .. } => "expired",
+            Self::Static => "static",
+        }
+    }
+}
+
+/// Notes kept about a MAC by the people running the network.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Annotation {
+    /// A friendly name to use instead of the hostname.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+impl Annotation {
+    /// Every text field, for searching.
+    pub fn texts(&self) -> impl Iterator<Item = &str> {
+        [&self.name, &self.owner, &self.location, &self.notes]
+            .into_iter()
+            .filter_map(Option::as_deref)
+            .chain(self.tags.iter().map(String::as_str))
+    }
+}
+
+/// When a MAC was first and last seen in any lease, across every reload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Sighting {
+    pub first_seen: Time,
+    pub last_seen: Time,
+    /// How many different addresses the MAC has leased.
+    pub distinct_addresses: u32,
+}
+
+impl Render for Sighting {
+    fn render(&mut self, format: TimeFormat) {
+        self.first_seen.render(format);
+        self.last_seen.render(format);
+    }
+}
+
+/// Timing derived from a lease and the current time. Durations are in whole
+/// seconds.
+#[derive(Debug, Default, Serialize)]
+pub struct LeaseTiming {
+    /// Time left until the lease ends, zero once it has expired.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remaining_seconds: Option<i64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_seconds: Option<i64>,
+
+    /// Time since the client last talked to the server (`cltt`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since_last_transaction_seconds: Option<i64>,
+
+    /// When the client is expected to renew (T1, half way through the lease).
+    #[serde(skip_serializing_if = "Time::is_none")]
+    renewal_at: Time,
+
+    /// When the client is expected to rebind (T2, 7/8 through the lease).
+    #[serde(skip_serializing_if = "Time::is_none")]
+    rebinding_at: Time,
+}
+
+impl Render for LeaseTiming {
+    fn render(&mut self, format: TimeFormat) {
+        self.renewal_at.render(format);
+        self.rebinding_at.render(format);
+    }
+}
+
+impl LeaseTiming {
+    pub fn new(lease: &Lease, now: DateTime<Utc>) -> Self {
+        let duration = lease.starts.zip(lease.ends).map(|(s, e)| e - s);
+        let at = |numerator: i32, denominator: i32| {
+            lease
+                .starts
+                .zip(duration)
+                .map(|(starts, duration)| starts + duration * numerator / denominator)
+        };
+
+        Self {
+            remaining_seconds: lease.ends.map(|ends| (ends - now).num_seconds().max(0)),
+            duration_seconds: duration.map(|d| d.num_seconds()),
+            since_last_transaction_seconds: lease.cltt.map(|cltt| (now - cltt).num_seconds()),
+            renewal_at: at(1, 2).into(),
+            rebinding_at: at(7, 8).into(),
+        }
+    }
+
+    pub fn remaining_seconds(&self) -> Option<i64> {
+        self.remaining_seconds
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct Device<'a> {
     address: &'a Ipv4Addr,
 
     hardware_ethernet: &'a MacAddr,
 
+    mac_kind: MacKind,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     hostname: Option<&'a str>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     vendor: Option<&'a str>,
 
+    /// Recorded history from before sources were tracked has none.
+    #[serde(skip_serializing_if = "str::is_empty")]
+    source: &'a str,
+
     lease: LeaseType,
 
+    /// When the lease started, to tell apart leases that are otherwise
+    /// the same.
+    #[serde(skip)]
+    starts: LeaseTime,
+
+    #[serde(skip_serializing_if = "Time::is_none")]
+    last_seen: Time,
+
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    timing: Option<LeaseTiming>,
+
+    /// What the lease history knows about this MAC.
     #[serde(skip_serializing_if = "Option::is_none")]
-    last_seen: LeaseTime,
+    sighting: Option<Sighting>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    annotation: Option<&'a Annotation>,
+}
+
+impl Render for Device<'_> {
+    fn render(&mut self, format: TimeFormat) {
+        self.lease.render(format);
+        self.last_seen.render(format);
+        self.timing.render(format);
+        self.sighting.render(format);
+    }
 }
 
 impl<'a> Device<'a> {
-    pub fn from_lease(lease: &'a Lease, vendor_mapping: &'a VendorMapping) -> Self {
-        let lease_type = if lease.is_expired() {
-            LeaseType::Expired { since: lease.ends }
+    pub fn from_lease(lease: &'a Lease, vendor: Option<&'a str>, now: DateTime<Utc>) -> Self {
+        let lease_type = if lease.is_expired(now) {
+            LeaseType::Expired {
+                since: lease.ends.into(),
+            }
         } else {
             LeaseType::Active {
-                since: lease.starts,
-                until: lease.ends,
+                since: lease.starts.into(),
+                until: lease.ends.into(),
             }
         };
 
-        let vendor = vendor_mapping.get_vendor_name(&lease.hardware_ethernet);
-
         Self {
             address: &lease.address,
             hardware_ethernet: &lease.hardware_ethernet,
+            mac_kind: lease.hardware_ethernet.kind(),
             hostname: lease.client_hostname.as_deref(),
             vendor,
+            source: &lease.source,
             lease: lease_type,
-            last_seen: lease.cltt,
+            starts: lease.starts,
+            last_seen: lease.cltt.into(),
+            timing: Some(LeaseTiming::new(lease, now)),
+            sighting: None,
+            annotation: None,
         }
     }
 
-    pub fn from_host(host: &'a Host, vendor_mapping: &'a VendorMapping) -> Self {
-        let vendor = vendor_mapping.get_vendor_name(&host.hardware_ethernet);
-
+    pub fn from_host(host: &'a Host, vendor: Option<&'a str>) -> Self {
         Self {
             address: &host.fixed_address,
             hardware_ethernet: &host.hardware_ethernet,
+            mac_kind: host.hardware_ethernet.kind(),
             hostname: host.hostname.as_deref(),
             vendor,
+            source: &host.source,
             lease: LeaseType::Static,
-            last_seen: None,
+            starts: None,
+            last_seen: Time::default(),
+            timing: None,
+            sighting: None,
+            annotation: None,
         }
     }
 
-    pub fn from_leases_and_hosts(
-        leases: &'a [&Lease],
-        hosts: &'a [&Host],
-        vendor_mapping: &'a VendorMapping,
-    ) -> Vec<Self> {
-        let mut devices = Vec::with_capacity(leases.len() + hosts.len());
+    #[must_use]
+    pub fn with_sighting(mut self, sighting: Option<&'a Sighting>) -> Self {
+        self.sighting = sighting.cloned();
+        self
+    }
 
-        for lease in leases {
-            devices.push(Self::from_lease(lease, vendor_mapping));
-        }
+    #[must_use]
+    pub fn with_annotation(mut self, annotation: Option<&'a Annotation>) -> Self {
+        self.annotation = annotation;
+        self
+    }
 
-        for host in hosts {
-            devices.push(Self::from_host(host, vendor_mapping));
-        }
+    pub fn address(&self) -> Ipv4Addr {
+        *self.address
+    }
+
+    pub fn hardware_ethernet(&self) -> &'a MacAddr {
+        self.hardware_ethernet
+    }
+
+    pub fn mac_kind(&self) -> MacKind {
+        self.mac_kind
+    }
+
+    pub fn hostname(&self) -> Option<&'a str> {
+        self.hostname
+    }
+
+    pub fn vendor(&self) -> Option<&'a str> {
+        self.vendor
+    }
+
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    pub fn lease(&self) -> &LeaseType {
+        &self.lease
+    }
 
-        devices
+    pub fn starts(&self) -> LeaseTime {
+        self.starts
+    }
+
+    pub fn last_seen(&self) -> LeaseTime {
+        self.last_seen.at()
+    }
+
+    pub fn annotation(&self) -> Option<&'a Annotation> {
+        self.annotation
+    }
+
+    pub fn timing(&self) -> Option<&LeaseTiming> {
+        self.timing.as_ref()
     }
 }
 
-pub trait FindByIp {
-    type Item;
+#[derive(Debug, Serialize)]
+pub struct StaticMapping<'a> {
+    address: &'a Ipv4Addr,
 
-    fn find_by_ip(&self, ip: Ipv4Addr) -> Vec<&Self::Item>;
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hostname: Option<&'a str>,
 }
 
-pub trait FindByMac {
-    type Item;
+#[derive(Debug, Serialize)]
+pub struct CurrentLease<'a> {
+    address: &'a Ipv4Addr,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hostname: Option<&'a str>,
 
-    fn find_by_mac(&self, mac: &MacAddr) -> Vec<&Self::Item>;
+    since: Time,
+    until: Time,
 }
 
-impl FindByIp for Vec<Lease> {
-    type Item = Lease;
+/// Everything known about a single MAC address, combining its static
+/// mapping with all of its leases.
+#[derive(Debug, Serialize)]
+pub struct MergedDevice<'a> {
+    hardware_ethernet: &'a MacAddr,
 
-    fn find_by_ip(&self, ip: Ipv4Addr) -> Vec<&Self::Item> {
-        self.iter().filter(|lease| lease.address == ip).collect()
+    mac_kind: MacKind,
+
+    /// Chosen from, in order: the static mapping, the current lease, then
+    /// the most recent past lease that sent a hostname.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hostname: Option<&'a str>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vendor: Option<&'a str>,
+
+    /// Every dhcpd instance that knows this MAC.
+    sources: Vec<&'a str>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    static_mapping: Option<StaticMapping<'a>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    current_lease: Option<CurrentLease<'a>>,
+
+    /// Addresses from older leases, most recent first.
+    past_addresses: Vec<&'a Ipv4Addr>,
+
+    #[serde(skip_serializing_if = "Time::is_none")]
+    last_seen: Time,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    annotation: Option<&'a Annotation>,
+}
+
+impl Render for MergedDevice<'_> {
+    fn render(&mut self, format: TimeFormat) {
+        if let Some(current) = &mut self.current_lease {
+            current.since.render(format);
+            current.until.render(format);
+        }
+        self.last_seen.render(format);
     }
 }
 
-impl FindByMac for Vec<Lease> {
-    type Item = Lease;
+impl<'a> MergedDevice<'a> {
+    pub fn new(
+        hardware_ethernet: &'a MacAddr,
+        mut leases: Vec<&'a Lease>,
+        hosts: &[&'a Host],
+        vendor: Option<&'a str>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        // Newest first; a lease that never started sorts last.
+        leases.sort_by_key(|lease| Reverse(lease.starts));
+
+        let static_mapping = hosts.first().map(|host| StaticMapping {
+            address: &host.fixed_address,
+            hostname: host.hostname.as_deref(),
+        });
+
+        let current = leases.iter().position(|lease| !lease.is_expired(now));
+        let current_lease = current.map(|i| CurrentLease {
+            address: &leases[i].address,
+            hostname: leases[i].client_hostname.as_deref(),
+            since: leases[i].starts.into(),
+            until: leases[i].ends.into(),
+        });
+
+        let mut past_addresses: Vec<&Ipv4Addr> = Vec::new();
+        for (i, lease) in leases.iter().enumerate() {
+            if Some(i) == current
+                || current_lease
+                    .as_ref()
+                    .is_some_and(|c| *c.address == lease.address)
+                || past_addresses.contains(&&lease.address)
+            {
+                continue;
+            }
+            past_addresses.push(&lease.address);
+        }
+
+        let hostname = static_mapping
+            .as_ref()
+            .and_then(|s| s.hostname)
+            .or_else(|| current_lease.as_ref().and_then(|c| c.hostname))
+            .or_else(|| {
+                leases
+                    .iter()
+                    .find_map(|lease| lease.client_hostname.as_deref())
+            });
+
+        let last_seen = leases.iter().filter_map(|lease| lease.cltt).max();
+
+        let mut sources = leases
+            .iter()
+            .map(|lease| lease.source.as_str())
+            .chain(hosts.iter().map(|host| host.source.as_str()))
+            .collect::<Vec<_>>();
+        sources.sort_unstable();
+        sources.dedup();
 
-    fn find_by_mac(&self, mac: &MacAddr) -> Vec<&Self::Item> {
-        self.iter()
-            .filter(|lease| lease.hardware_ethernet == *mac)
-            .collect()
+        Self {
+            hardware_ethernet,
+            mac_kind: hardware_ethernet.kind(),
+            hostname,
+            vendor,
+            sources,
+            static_mapping,
+            current_lease,
+            past_addresses,
+            last_seen: last_seen.into(),
+            annotation: None,
+        }
     }
-}
 
-impl FindByIp for Vec<Host> {
-    type Item = Host;
+    #[must_use]
+    pub fn hardware_ethernet(&self) -> &'a MacAddr {
+        self.hardware_ethernet
+    }
 
-    fn find_by_ip(&self, ip: Ipv4Addr) -> Vec<&Self::Item> {
-        self.iter()
-            .filter(|host| host.fixed_address == ip)
-            .collect()
+    pub fn with_annotation(mut self, annotation: Option<&'a Annotation>) -> Self {
+        self.annotation = annotation;
+        self
     }
 }
 
-impl FindByMac for Vec<Host> {
-    type Item = Host;
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+    use crate::clock::{Clock, FixedClock};
+
+    fn clock() -> FixedClock {
+        FixedClock(Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap())
+    }
+
+    /// A lease of 10.0.0.`d` to 01:02:03:04:05:06 between `starts` and
+    /// `ends` hours from now.
+    fn lease(d: u8, starts: i64, ends: i64) -> Lease {
+        let now = clock().now();
+        let starts = now + Duration::hours(starts);
+        Lease::test(d)
+            .with_mac([1, 2, 3, 4, 5, 6])
+            .with_times(starts, now + Duration::hours(ends))
+            .with_cltt(starts)
+    }
+
+    #[test]
+    fn test_merged_device_without_static_mapping() {
+        let mac = MacAddr::from([1, 2, 3, 4, 5, 6]);
+        let leases = [
+            lease(5, -48, -24).with_hostname("old-name"),
+            lease(7, -2, 10),
+            lease(6, -24, -12),
+            lease(5, -72, -60),
+        ];
+        let device = MergedDevice::new(&mac, leases.iter().collect(), &[], None, clock().now());
+
+        assert_eq!(
+            device.current_lease.as_ref().map(|c| *c.address),
+            Some(Ipv4Addr::new(10, 0, 0, 7))
+        );
+        assert_eq!(
+            device.past_addresses,
+            vec![&Ipv4Addr::new(10, 0, 0, 6), &Ipv4Addr::new(10, 0, 0, 5)]
+        );
+        assert_eq!(device.hostname, Some("old-name"));
+        assert_eq!(device.last_seen.at(), leases[1].cltt);
+        assert_eq!(device.mac_kind, mac.kind());
+    }
+
+    #[test]
+    fn test_merged_device_prefers_static_hostname() {
+        let mac = MacAddr::from([1, 2, 3, 4, 5, 6]);
+        let leases = [lease(7, -2, 10).with_hostname("android-8f3c2a")];
+        let host = Host {
+            label: "s_lan_0".to_string(),
+            fixed_address: Ipv4Addr::new(10, 0, 0, 7),
+            hardware_ethernet: mac.clone(),
+            hostname: Some("phone".to_string()),
+            source: String::new(),
+        };
+        let device = MergedDevice::new(
+            &mac,
+            leases.iter().collect(),
+            &[&host],
+            Some("Acme"),
+            clock().now(),
+        );
+
+        assert_eq!(device.hostname, Some("phone"));
+        assert!(device.past_addresses.is_empty());
+
+        let expired = [lease(7, -20, -10).with_hostname("android-8f3c2a")];
+        let device = MergedDevice::new(&mac, expired.iter().collect(), &[], None, clock().now());
+        assert!(device.current_lease.is_none());
+        assert_eq!(device.hostname, Some("android-8f3c2a"));
+    }
+
+    #[test]
+    fn test_lease_timing() {
+        let now = clock().now();
+        let timing = LeaseTiming::new(&lease(7, -2, 6), now);
+
+        assert_eq!(timing.remaining_seconds, Some(6 * 3600));
+        assert_eq!(timing.duration_seconds, Some(8 * 3600));
+        assert_eq!(timing.since_last_transaction_seconds, Some(2 * 3600));
+        assert_eq!(timing.renewal_at.at(), Some(now + Duration::hours(2)));
+        assert_eq!(timing.rebinding_at.at(), Some(now + Duration::hours(5)));
 
-    fn find_by_mac(&self, mac: &MacAddr) -> Vec<&Self::Item> {
-        self.iter()
-            .filter(|host| host.hardware_ethernet == *mac)
-            .collect()
+        let expired = LeaseTiming::new(&lease(7, -20, -10), now);
+        assert_eq!(expired.remaining_seconds, Some(0));
     }
 }
@@ -0,0 +1,559 @@
+//! A small query language for selecting devices, e.g.
+//!
+//! ```text
+//! vendor:apple AND state:active AND subnet:10.0.0.0/24 AND NOT hostname:*iphone*
+//! last_seen>-1h OR (ip>=10.0.5.0 ip<10.0.6.0)
+//! ```
+//!
+//! Terms are `field` `op` `value`, where op is one of `:`, `=`, `>`, `>=`,
+//! `<` or `<=`. Terms are combined with `AND`, `OR`, `NOT` and parentheses;
+//! terms separated only by whitespace must all match.
+
+use std::{net::Ipv4Addr, str::FromStr};
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, tag_no_case, take_till, take_while1},
+    character::complete::{char, multispace0, multispace1},
+    combinator::{all_consuming, cut, map, not, opt, peek},
+    error::ErrorKind,
+    multi::many0,
+    sequence::{delimited, preceded, terminated, tuple},
+    Finish, IResult,
+};
+use serde::Serialize;
+
+use crate::{
+    cidr::Ipv4Cidr,
+    model::{Device, MacKind},
+    search::{Matcher, Mode},
+};
+
+#[derive(Debug, thiserror::Error, Serialize)]
+#[error("{message} at position {position}")]
+pub struct ParseError {
+    pub message: String,
+    pub position: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl Op {
+    fn test<T: PartialOrd>(self, left: &T, right: &T) -> bool {
+        match self {
+            Self::Eq => left == right,
+            Self::Gt => left > right,
+            Self::Ge => left >= right,
+            Self::Lt => left < right,
+            Self::Le => left <= right,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TextField {
+    Hostname,
+    Vendor,
+    Mac,
+    Ip,
+    /// Any of the annotation's text fields.
+    Annotation,
+    Tag,
+}
+
+#[derive(Debug)]
+pub enum TimeValue {
+    /// An offset from the time of evaluation, e.g. `-1h`.
+    Relative(Duration),
+    Absolute(DateTime<Utc>),
+}
+
+impl TimeValue {
+    /// The time this is at `now`, or `None` if an offset takes it out of
+    /// range.
+    pub fn resolve(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Relative(offset) => now.checked_add_signed(*offset),
+            Self::Absolute(time) => Some(*time),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Predicate {
+    Text(TextField, Matcher),
+    LeaseType(&'static str),
+    MacKind(&'static str),
+    Subnet(Ipv4Cidr),
+    Ip(Op, Ipv4Addr),
+    LastSeen(Op, TimeValue),
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Not(Box<Expr>),
+    Predicate(Predicate),
+}
+
+impl FromStr for Expr {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (_, raw) = all_consuming(terminated(|i| or_expr(i, 0), multispace0))(s)
+            .finish()
+            .map_err(|e| ParseError {
+                message: if e.code == ErrorKind::TooLarge {
+                    format!("query nested more than {MAX_DEPTH} levels deep")
+                } else if e.input.is_empty() {
+                    "unexpected end of query".to_string()
+                } else {
+                    format!("expected a term like field:value, found {:?}", e.input)
+                },
+                position: s.len() - e.input.len(),
+            })?;
+
+        Self::build(s, raw)
+    }
+}
+
+impl Expr {
+    pub fn matches(&self, device: &Device, now: DateTime<Utc>) -> bool {
+        match self {
+            Self::And(exprs) => exprs.iter().all(|e| e.matches(device, now)),
+            Self::Or(exprs) => exprs.iter().any(|e| e.matches(device, now)),
+            Self::Not(expr) => !expr.matches(device, now),
+            Self::Predicate(predicate) => predicate.matches(device, now),
+        }
+    }
+
+    /// Check that every time in the expression can be resolved at `now`.
+    pub fn check_times(&self, now: DateTime<Utc>) -> Result<(), String> {
+        match self {
+            Self::And(exprs) | Self::Or(exprs) => exprs.iter().try_for_each(|e| e.check_times(now)),
+            Self::Not(expr) => expr.check_times(now),
+            Self::Predicate(Predicate::LastSeen(_, time)) => match time.resolve(now) {
+                Some(_) => Ok(()),
+                None => Err("last_seen time is out of range".to_owned()),
+            },
+            Self::Predicate(_) => Ok(()),
+        }
+    }
+
+    fn build(input: &str, raw: Raw) -> Result<Self, ParseError> {
+        let build_all = |raws: Vec<Raw>| {
+            raws.into_iter()
+                .map(|raw| Self::build(input, raw))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(match raw {
+            Raw::And(raws) => Self::And(build_all(raws)?),
+            Raw::Or(raws) => Self::Or(build_all(raws)?),
+            Raw::Not(raw) => Self::Not(Box::new(Self::build(input, *raw)?)),
+            Raw::Term { field, op, value } => {
+                // `field` is a slice of `input`, so this is its offset.
+                let position = field.as_ptr() as usize - input.as_ptr() as usize;
+                let error = |message: String| ParseError { message, position };
+                Self::Predicate(Predicate::new(field, op, &value).map_err(error)?)
+            }
+        })
+    }
+}
+
+impl Predicate {
+    fn new(field: &str, op: Op, value: &str) -> Result<Self, String> {
+        let equality = |predicate: Predicate| {
+            if op == Op::Eq {
+                Ok(predicate)
+            } else {
+                Err(format!("{field} only supports ':' or '='"))
+            }
+        };
+
+        match field.to_lowercase().as_str() {
+            "hostname" => equality(Self::text(TextField::Hostname, value)?),
+            "vendor" => equality(Self::text(TextField::Vendor, value)?),
+            "mac" => equality(Self::text(TextField::Mac, value)?),
+            "annotation" => equality(Self::text(TextField::Annotation, value)?),
+            "tag" => equality(Self::text(TextField::Tag, value)?),
+            "ip" if op == Op::Eq && value.contains(['*', '?']) => {
+                Ok(Self::text(TextField::Ip, value)?)
+            }
+            "ip" => {
+                let ip = value
+                    .parse::<Ipv4Addr>()
+                    .map_err(|e| format!("invalid ip {value:?}: {e}"))?;
+                Ok(Self::Ip(op, ip))
+            }
+            "subnet" => {
+                let cidr = value
+                    .parse::<Ipv4Cidr>()
+                    .map_err(|e| format!("invalid subnet {value:?}: {e}"))?;
+                equality(Self::Subnet(cidr))
+            }
+            "state" | "type" => {
+                let lease_type = ["active", "expired", "static"]
+                    .into_iter()
+                    .find(|t| t.eq_ignore_ascii_case(value))
+                    .ok_or_else(|| format!("unknown {field} {value:?}"))?;
+                equality(Self::LeaseType(lease_type))
+            }
+            "mac_kind" => {
+                let kind = MacKind::NAMES
+                    .into_iter()
+                    .find(|k| k.eq_ignore_ascii_case(value))
+                    .ok_or_else(|| format!("unknown {field} {value:?}"))?;
+                equality(Self::MacKind(kind))
+            }
+            "last_seen" => {
+                if op == Op::Eq {
+                    return Err(format!("{field} only supports >, >=, < or <="));
+                }
+                Ok(Self::LastSeen(op, parse_time(value)?))
+            }
+            _ => Err(format!("unknown field {field:?}")),
+        }
+    }
+
+    fn text(field: TextField, value: &str) -> Result<Self, String> {
+        let mode = if value.contains(['*', '?', '[']) {
+            Mode::Glob
+        } else {
+            Mode::Substring
+        };
+        let matcher = Matcher::new(value, mode).map_err(|e| e.to_string())?;
+        Ok(Self::Text(field, matcher))
+    }
+
+    fn matches(&self, device: &Device, now: DateTime<Utc>) -> bool {
+        match self {
+            Self::Text(field, matcher) => {
+                let hit = |text: &str| matcher.score(&text.to_lowercase()).is_some();
+                match field {
+                    TextField::Hostname => device.hostname().is_some_and(hit),
+                    TextField::Vendor => device.vendor().is_some_and(hit),
+                    TextField::Mac => hit(&device.hardware_ethernet().to_string()),
+                    TextField::Ip => hit(&device.address().to_string()),
+                    TextField::Annotation => device
+                        .annotation()
+                        .is_some_and(|annotation| annotation.texts().any(hit)),
+                    TextField::Tag => device
+                        .annotation()
+                        .is_some_and(|annotation| annotation.tags.iter().any(|tag| hit(tag))),
+                }
+            }
+            Self::LeaseType(lease_type) => device.lease().name() == *lease_type,
+            Self::MacKind(kind) => device.mac_kind().is(kind),
+            Self::Subnet(cidr) => cidr.contains(device.address()),
+            Self::Ip(op, ip) => op.test(&device.address(), ip),
+            Self::LastSeen(op, time) => device
+                .last_seen()
+                .zip(time.resolve(now))
+                .is_some_and(|(last_seen, time)| op.test(&last_seen, &time)),
+        }
+    }
+}
+
+/// A length of time such as `90s`, `30m`, `1h`, `2d` or `1w`.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let Some((split, unit)) = value.char_indices().last() else {
+        return Err("empty duration".to_owned());
+    };
+    let amount = &value[..split];
+    // A sign here would be a second one after `parse_time`'s, as in `--5d`.
+    if !amount.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(format!("invalid duration {value:?}"));
+    }
+    let amount = amount
+        .parse::<i64>()
+        .map_err(|_| format!("invalid duration {value:?}"))?;
+    let duration = match unit {
+        's' => Duration::try_seconds(amount),
+        'm' => Duration::try_minutes(amount),
+        'h' => Duration::try_hours(amount),
+        'd' => Duration::try_days(amount),
+        'w' => Duration::try_weeks(amount),
+        _ => {
+            return Err(format!(
+                "invalid time unit in {value:?}, use s, m, h, d or w"
+            ))
+        }
+    };
+
+    duration.ok_or_else(|| format!("duration {value:?} is out of range"))
+}
+
+/// Either `now`, an offset such as `-90m`, `-1h`, `-2d` or `-1w`, an RFC 3339
+/// timestamp or a plain date.
+pub fn parse_time(value: &str) -> Result<TimeValue, String> {
+    if value.eq_ignore_ascii_case("now") {
+        return Ok(TimeValue::Relative(Duration::zero()));
+    }
+    if let Some(offset) = value.strip_prefix(['-', '+']) {
+        let duration = parse_duration(offset)?;
+        let duration = if value.starts_with('-') {
+            -duration
+        } else {
+            duration
+        };
+        return Ok(TimeValue::Relative(duration));
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(TimeValue::Absolute(time.with_timezone(&Utc)));
+    }
+    if let Some(midnight) = NaiveDate::from_str(value)
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+    {
+        return Ok(TimeValue::Absolute(midnight.and_utc()));
+    }
+
+    Err(format!("invalid time {value:?}"))
+}
+
+/// The syntax tree before field names and values are checked.
+#[derive(Debug)]
+enum Raw<'a> {
+    And(Vec<Raw<'a>>),
+    Or(Vec<Raw<'a>>),
+    Not(Box<Raw<'a>>),
+    Term {
+        field: &'a str,
+        op: Op,
+        value: String,
+    },
+}
+
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    terminated(
+        tag_no_case(word),
+        peek(not(take_while1(|c: char| c.is_alphanumeric() || c == '_'))),
+    )
+}
+
+/// How deeply parentheses and `NOT`s may nest. Parsing, and everything
+/// else done with the tree, recurses once per level.
+const MAX_DEPTH: usize = 64;
+
+fn or_expr(input: &str, depth: usize) -> IResult<&str, Raw<'_>> {
+    let (input, first) = and_expr(input, depth)?;
+    let (input, mut rest) = many0(preceded(tuple((multispace1, keyword("OR"))), |i| {
+        and_expr(i, depth)
+    }))(input)?;
+
+    if rest.is_empty() {
+        return Ok((input, first));
+    }
+    rest.insert(0, first);
+    Ok((input, Raw::Or(rest)))
+}
+
+fn and_expr(input: &str, depth: usize) -> IResult<&str, Raw<'_>> {
+    let (input, first) = unary(input, depth)?;
+    let (input, mut rest) = many0(preceded(opt(tuple((multispace1, keyword("AND")))), |i| {
+        unary(i, depth)
+    }))(input)?;
+
+    if rest.is_empty() {
+        return Ok((input, first));
+    }
+    rest.insert(0, first);
+    Ok((input, Raw::And(rest)))
+}
+
+fn unary(input: &str, depth: usize) -> IResult<&str, Raw<'_>> {
+    let (input, _) = multispace0(input)?;
+    if depth > MAX_DEPTH {
+        return Err(nom::Err::Failure(nom::error::Error::new(
+            input,
+            ErrorKind::TooLarge,
+        )));
+    }
+    alt((
+        map(
+            preceded(terminated(keyword("NOT"), multispace0), |i| {
+                unary(i, depth + 1)
+            }),
+            |raw| Raw::Not(Box::new(raw)),
+        ),
+        delimited(
+            char('('),
+            cut(terminated(|i| or_expr(i, depth + 1), multispace0)),
+            cut(char(')')),
+        ),
+        term,
+    ))(input)
+}
+
+fn term(input: &str) -> IResult<&str, Raw<'_>> {
+    let (input, field) = take_while1(|c: char| c.is_ascii_alphanumeric() || c == '_')(input)?;
+    let (input, op) = alt((
+        map(tag(">="), |_| Op::Ge),
+        map(tag("<="), |_| Op::Le),
+        map(tag(">"), |_| Op::Gt),
+        map(tag("<"), |_| Op::Lt),
+        map(alt((tag(":"), tag("="))), |_| Op::Eq),
+    ))(input)?;
+    let (input, value) = alt((
+        map(
+            delimited(char('"'), take_till(|c| c == '"'), char('"')),
+            str::to_owned,
+        ),
+        map(
+            take_while1(|c: char| !c.is_whitespace() && c != '(' && c != ')'),
+            str::to_owned,
+        ),
+    ))(input)?;
+
+    Ok((input, Raw::Term { field, op, value }))
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use super::*;
+    use crate::model::{Annotation, Host, Lease, MacAddr};
+
+    fn lease(d: u8, minutes_ago: i64, hostname: &str) -> Lease {
+        let now = Utc::now();
+        let starts = now - Duration::minutes(minutes_ago);
+        Lease::test(d)
+            .with_mac([0xf0, 0xb3, 0xec, 0, 0, d])
+            .with_hostname(hostname)
+            .with_times(starts, now + Duration::hours(1))
+            .with_cltt(starts)
+    }
+
+    fn select(query: &str, leases: &[Lease], hosts: &[Host]) -> Vec<u8> {
+        let expr = Expr::from_str(query).unwrap();
+        let now = Utc::now();
+        leases
+            .iter()
+            .map(|l| Device::from_lease(l, Some("Apple, Inc."), now))
+            .chain(hosts.iter().map(|h| Device::from_host(h, None)))
+            .filter(|d| expr.matches(d, now))
+            .map(|d| d.address().octets()[3])
+            .collect()
+    }
+
+    #[test]
+    fn test_queries() {
+        let leases = [
+            lease(2, 10, "Dylans-iPhone"),
+            lease(3, 120, "macbook"),
+            lease(4, 5, "ipad"),
+        ];
+        let hosts = [Host {
+            label: "s_lan_0".to_string(),
+            fixed_address: Ipv4Addr::new(10, 0, 1, 1),
+            hardware_ethernet: MacAddr::from([1, 2, 3, 4, 5, 6]),
+            hostname: Some("router".to_string()),
+            source: String::new(),
+        }];
+
+        assert_eq!(
+            select(
+                "vendor:apple AND state:active AND subnet:10.0.0.0/24 AND NOT hostname:*iphone*",
+                &leases,
+                &hosts
+            ),
+            vec![3, 4]
+        );
+        assert_eq!(select("last_seen>-1h", &leases, &hosts), vec![2, 4]);
+        assert_eq!(
+            select("type:static OR (ip>=10.0.0.3 ip<10.0.0.4)", &leases, &hosts),
+            vec![3, 1]
+        );
+        assert_eq!(select("mac:f0:b3:ec:00:00:02", &leases, &hosts), vec![2]);
+        assert_eq!(select("mac_kind:multicast", &leases, &hosts), vec![1]);
+        assert_eq!(select("ip:10.0.1.*", &leases, &hosts), vec![1]);
+        assert_eq!(select(r#"hostname:"IPAD""#, &leases, &hosts), vec![4]);
+        assert_eq!(select("not (vendor:apple)", &leases, &hosts), vec![1]);
+    }
+
+    #[test]
+    fn test_annotation_queries() {
+        let lease = lease(2, 10, "android-8f3c2a");
+        let annotation = Annotation {
+            name: Some("Kitchen tablet".to_string()),
+            tags: vec!["iot".to_string(), "family".to_string()],
+            ..Annotation::default()
+        };
+        let device =
+            Device::from_lease(&lease, None, Utc::now()).with_annotation(Some(&annotation));
+        let matches = |query: &str| Expr::from_str(query).unwrap().matches(&device, Utc::now());
+
+        assert!(matches("annotation:kitchen"));
+        assert!(matches("tag:IOT"));
+        assert!(!matches("tag:kitchen"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let e = Expr::from_str("vendor:apple AND colour:red").unwrap_err();
+        assert_eq!(e.position, 17);
+        assert!(e.message.contains("colour"));
+
+        let e = Expr::from_str("vendor:apple AND (state:active").unwrap_err();
+        assert_eq!(e.position, 30);
+
+        let e = Expr::from_str("vendor:apple AND OR state:active").unwrap_err();
+        assert_eq!(e.position, 13);
+
+        let e = Expr::from_str("last_seen:-1h").unwrap_err();
+        assert_eq!(e.position, 0);
+
+        let e = Expr::from_str("last_seen>-1y").unwrap_err();
+        assert!(e.message.contains("unit"));
+
+        let e = Expr::from_str("").unwrap_err();
+        assert_eq!(e.message, "unexpected end of query");
+
+        // Too deep to recurse into, however it is nested.
+        let parens = format!("{}ip:10.0.0.1{}", "(".repeat(20_000), ")".repeat(20_000));
+        let e = Expr::from_str(&parens).unwrap_err();
+        assert!(e.message.contains("nested"), "{}", e.message);
+        let nots = "NOT ".repeat(20_000) + "ip:10.0.0.1";
+        let e = Expr::from_str(&nots).unwrap_err();
+        assert!(e.message.contains("nested"), "{}", e.message);
+
+        let ok = format!(
+            "{}ip:10.0.0.1{}",
+            "(".repeat(MAX_DEPTH),
+            ")".repeat(MAX_DEPTH)
+        );
+        assert!(Expr::from_str(&ok).is_ok());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert!(matches!(parse_time("now"), Ok(TimeValue::Relative(d)) if d.is_zero()));
+        assert!(
+            matches!(parse_time("-90m"), Ok(TimeValue::Relative(d)) if d == Duration::minutes(-90))
+        );
+        assert!(matches!(
+            parse_time("2024-01-02T03:04:05Z"),
+            Ok(TimeValue::Absolute(_))
+        ));
+        assert!(matches!(
+            parse_time("2024-01-02"),
+            Ok(TimeValue::Absolute(_))
+        ));
+        parse_time("yesterday").expect_err("Invalid time");
+        parse_time("-").expect_err("Empty offset");
+        parse_time("--5d").expect_err("Two signs");
+        parse_time("-+5d").expect_err("Two signs");
+        parse_time("-99999999999999w").expect_err("Out of range");
+        parse_duration("-5d").expect_err("Negative duration");
+    }
+}
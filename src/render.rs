@@ -0,0 +1,213 @@
+use axum::{
+    async_trait,
+    extract::{rejection::QueryRejection, FromRequestParts, Query},
+    http::request::Parts,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
+
+use crate::model::LeaseTime;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unknown timezone: {0}")]
+    UnknownTimezone(String),
+
+    #[error(transparent)]
+    Query(#[from] QueryRejection),
+}
+
+/// The zone from `--timezone`, used when a request doesn't pass `?tz=`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultTimezone(pub Option<Tz>);
+
+#[derive(Debug, Deserialize)]
+struct FormatQuery {
+    tz: Option<String>,
+
+    #[serde(default)]
+    epoch: bool,
+}
+
+/// How timestamps are written in a response. Stored times are always UTC;
+/// this only changes how they are serialized.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeFormat {
+    /// `None` keeps the plain UTC rendering.
+    tz: Option<Tz>,
+
+    /// Render `{"time": ..., "epoch": ...}` instead of just the time.
+    epoch: bool,
+}
+
+impl TimeFormat {
+    /// `value` with its times rendered in this format.
+    pub fn apply<T: Render>(self, mut value: T) -> T {
+        value.render(self);
+        value
+    }
+
+    fn write<S: Serializer>(self, time: DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        let text = match self.tz {
+            Some(tz) => time
+                .with_timezone(&tz)
+                .to_rfc3339_opts(SecondsFormat::AutoSi, false),
+            None => time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        };
+        if !self.epoch {
+            return serializer.serialize_str(&text);
+        }
+
+        let mut state = serializer.serialize_struct("Time", 2)?;
+        state.serialize_field("time", &text)?;
+        state.serialize_field("epoch", &time.timestamp())?;
+        state.end()
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for TimeFormat
+where
+    S: Send + Sync,
+{
+    type Rejection = crate::Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<FormatQuery>::from_request_parts(parts, state)
+            .await
+            .map_err(Error::from)?;
+        let tz = match query.tz.as_deref().filter(|tz| !tz.is_empty()) {
+            Some(tz) => Some(
+                tz.parse::<Tz>()
+                    .map_err(|_| Error::UnknownTimezone(tz.to_owned()))?,
+            ),
+            None => parts
+                .extensions
+                .get::<DefaultTimezone>()
+                .and_then(|default| default.0),
+        };
+
+        Ok(Self {
+            tz,
+            epoch: query.epoch,
+        })
+    }
+}
+
+/// A time in a response. It is written in UTC until it is rendered in a
+/// request's [`TimeFormat`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Time {
+    at: LeaseTime,
+    format: TimeFormat,
+}
+
+impl Time {
+    pub fn at(&self) -> LeaseTime {
+        self.at
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.at.is_none()
+    }
+}
+
+impl From<LeaseTime> for Time {
+    fn from(at: LeaseTime) -> Self {
+        Self {
+            at,
+            format: TimeFormat::default(),
+        }
+    }
+}
+
+impl From<DateTime<Utc>> for Time {
+    fn from(at: DateTime<Utc>) -> Self {
+        Self::from(Some(at))
+    }
+}
+
+/// Times are the same if they are the same instant, however they are written.
+impl PartialEq for Time {
+    fn eq(&self, other: &Self) -> bool {
+        self.at == other.at
+    }
+}
+
+impl Eq for Time {}
+
+impl Serialize for Time {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.at {
+            Some(time) => self.format.write(time, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+/// Something with [`Time`]s in it, to be written in a request's
+/// [`TimeFormat`]. Implementations render every time they serialize.
+pub trait Render {
+    fn render(&mut self, format: TimeFormat);
+}
+
+impl Render for Time {
+    fn render(&mut self, format: TimeFormat) {
+        self.format = format;
+    }
+}
+
+impl<T: Render> Render for Option<T> {
+    fn render(&mut self, format: TimeFormat) {
+        if let Some(value) = self {
+            value.render(format);
+        }
+    }
+}
+
+impl<T: Render> Render for Vec<T> {
+    fn render(&mut self, format: TimeFormat) {
+        for value in self {
+            value.render(format);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_render_time() {
+        let time = Time::from(Utc.with_ymd_and_hms(2024, 7, 1, 10, 0, 0).unwrap());
+
+        assert_eq!(json!(time), json!("2024-07-01T10:00:00Z"));
+
+        let zoned = TimeFormat {
+            tz: Some(chrono_tz::Europe::Amsterdam),
+            epoch: false,
+        };
+        assert_eq!(json!(zoned.apply(time)), json!("2024-07-01T12:00:00+02:00"));
+
+        let epoch = TimeFormat {
+            tz: Some(chrono_tz::UTC),
+            epoch: true,
+        };
+        assert_eq!(
+            json!(epoch.apply(vec![Some(time), Some(Time::default()), None])),
+            json!([
+                { "time": "2024-07-01T10:00:00+00:00", "epoch": 1_719_828_000 },
+                null,
+                null,
+            ])
+        );
+
+        // Only what the format was applied to.
+        assert_eq!(json!(time), json!("2024-07-01T10:00:00Z"));
+    }
+}
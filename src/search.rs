@@ -0,0 +1,156 @@
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+/// Minimum Jaro-Winkler similarity for a fuzzy match.
+const FUZZY_THRESHOLD: f64 = 0.8;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("empty search query")]
+    Empty,
+
+    #[error("invalid pattern: {0}")]
+    Pattern(#[from] regex::Error),
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Substring,
+    Glob,
+    Regex,
+    Fuzzy,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    /// The text to search for. Unlike the listings, `q` here isn't a query
+    /// language expression.
+    pub q: String,
+
+    #[serde(default)]
+    pub mode: Mode,
+}
+
+/// A compiled search query. Every mode is case-insensitive.
+#[derive(Debug)]
+pub enum Matcher {
+    Substring(String),
+    Regex(Regex),
+    Fuzzy(String),
+}
+
+impl Matcher {
+    pub fn new(query: &str, mode: Mode) -> Result<Self, Error> {
+        let query = query.trim();
+        if query.is_empty() {
+            return Err(Error::Empty);
+        }
+
+        let matcher = match mode {
+            Mode::Substring => Self::Substring(query.to_lowercase()),
+            Mode::Glob => Self::Regex(case_insensitive(&glob_to_regex(query))?),
+            Mode::Regex => Self::Regex(case_insensitive(query)?),
+            Mode::Fuzzy => Self::Fuzzy(query.to_lowercase()),
+        };
+
+        Ok(matcher)
+    }
+
+    /// Score `name` against the query, higher is better. `None` means no match.
+    /// `name` is expected to be lowercase already, as stored in the index.
+    pub fn score(&self, name: &str) -> Option<f64> {
+        match self {
+            Self::Substring(needle) => name.contains(needle.as_str()).then_some(1.0),
+            Self::Regex(re) => re.is_match(name).then_some(1.0),
+            Self::Fuzzy(needle) => {
+                let score = name
+                    .split(['-', '_', '.', ' '])
+                    .chain(std::iter::once(name))
+                    .map(|word| strsim::jaro_winkler(needle, word))
+                    .fold(0.0, f64::max);
+                (score >= FUZZY_THRESHOLD).then_some(score)
+            }
+        }
+    }
+}
+
+fn case_insensitive(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).case_insensitive(true).build()
+}
+
+/// Translate a shell glob (`*`, `?` and `[...]`) into an anchored regex.
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::with_capacity(glob.len() + 2);
+    let mut in_class = false;
+    re.push('^');
+    for c in glob.chars() {
+        match c {
+            '*' if !in_class => re.push_str(".*"),
+            '?' if !in_class => re.push('.'),
+            '[' if !in_class => {
+                in_class = true;
+                re.push('[');
+            }
+            ']' if in_class => {
+                in_class = false;
+                re.push(']');
+            }
+            '!' if in_class && re.ends_with('[') => re.push('^'),
+            _ if in_class => re.push(c),
+            _ => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    re.push('$');
+    re
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use super::*;
+
+    #[test]
+    fn test_substring() {
+        let m = Matcher::new("TV", Mode::Substring).unwrap();
+        assert!(m.score("living-room-tv").is_some());
+        assert!(m.score("laptop").is_none());
+    }
+
+    #[test]
+    fn test_glob() {
+        assert_eq!(glob_to_regex("*iphone?"), "^.*iphone.$");
+        assert_eq!(glob_to_regex("a.b[!0-9]"), r"^a\.b[^0-9]$");
+
+        let m = Matcher::new("living*", Mode::Glob).unwrap();
+        assert!(m.score("living-room-tv").is_some());
+        assert!(m.score("the-living-room").is_none());
+    }
+
+    #[test]
+    fn test_regex() {
+        let m = Matcher::new("^android-[0-9a-f]+$", Mode::Regex).unwrap();
+        assert!(m.score("android-8f3c2a").is_some());
+        assert!(m.score("android-phone").is_none());
+
+        Matcher::new("(", Mode::Regex).expect_err("Invalid regex");
+    }
+
+    #[test]
+    fn test_fuzzy() {
+        let m = Matcher::new("livingrom", Mode::Fuzzy).unwrap();
+        let score = m.score("livingroom-tv").unwrap();
+        assert!(score > FUZZY_THRESHOLD);
+        assert!(m.score("printer").is_none());
+
+        let exact = Matcher::new("printer", Mode::Fuzzy).unwrap();
+        let typo = Matcher::new("pritner", Mode::Fuzzy).unwrap();
+        assert!(exact.score("office-printer") > typo.score("office-printer"));
+    }
+
+    #[test]
+    fn test_empty_query() {
+        Matcher::new("  ", Mode::Substring).expect_err("Empty query");
+    }
+}
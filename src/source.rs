@@ -0,0 +1,150 @@
+use std::{path::PathBuf, str::FromStr, sync::Arc};
+
+use axum::{
+    async_trait,
+    extract::{rejection::QueryRejection, FromRequestParts, Query},
+    http::request::Parts,
+};
+use serde::Deserialize;
+
+use crate::model::Device;
+
+/// The source name used when only `--dhcpd-leases` and `--dhcpd-config` are
+/// given.
+pub const DEFAULT_SOURCE: &str = "default";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("expected NAME=LEASES,CONFIG, got {0:?}")]
+    InvalidSource(String),
+
+    #[error("source {0} is given more than once")]
+    DuplicateSource(String),
+
+    #[error("unknown source: {0}")]
+    UnknownSource(String),
+
+    #[error(transparent)]
+    Query(#[from] QueryRejection),
+}
+
+/// One dhcpd instance: a name for it and the files it uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceConfig {
+    pub name: String,
+    pub leases: PathBuf,
+    pub config: PathBuf,
+}
+
+impl FromStr for SourceConfig {
+    type Err = Error;
+
+    /// Parses `NAME=LEASES,CONFIG`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidSource(s.to_owned());
+        let (name, paths) = s.split_once('=').ok_or_else(invalid)?;
+        let (leases, config) = paths.split_once(',').ok_or_else(invalid)?;
+        if name.is_empty() || leases.is_empty() || config.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            leases: leases.into(),
+            config: config.into(),
+        })
+    }
+}
+
+/// The configured source names, so that `?source=` can reject unknown ones.
+#[derive(Debug, Clone, Default)]
+pub struct SourceNames(pub Arc<[String]>);
+
+#[derive(Debug, Deserialize)]
+struct SourceQuery {
+    source: Option<String>,
+}
+
+/// `?source=` limits a response to one dhcpd instance.
+#[derive(Debug, Clone, Default)]
+pub struct SourceFilter(Option<String>);
+
+impl SourceFilter {
+    /// Only `name`, which must be one of `names`. No name or an empty one
+    /// allows every source.
+    pub fn new(name: Option<String>, names: &SourceNames) -> Result<Self, Error> {
+        let Some(name) = name.filter(|name| !name.is_empty()) else {
+            return Ok(Self(None));
+        };
+        if !names.0.contains(&name) {
+            return Err(Error::UnknownSource(name));
+        }
+
+        Ok(Self(Some(name)))
+    }
+
+    pub fn matches(&self, source: &str) -> bool {
+        match &self.0 {
+            Some(name) => name == source,
+            None => true,
+        }
+    }
+
+    /// The source allowed, or `None` for all of them.
+    pub fn name(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+
+    pub fn is_all(&self) -> bool {
+        self.0.is_none()
+    }
+
+    pub fn retain<'a>(&self, mut devices: Vec<Device<'a>>) -> Vec<Device<'a>> {
+        if !self.is_all() {
+            devices.retain(|device| self.matches(device.source()));
+        }
+        devices
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SourceFilter
+where
+    S: Send + Sync,
+{
+    type Rejection = crate::Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<SourceQuery>::from_request_parts(parts, state)
+            .await
+            .map_err(Error::from)?;
+        let names = parts.extensions.get::<SourceNames>().cloned();
+
+        Ok(Self::new(query.source, &names.unwrap_or_default())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use super::*;
+
+    #[test]
+    fn test_parse_source() {
+        assert_eq!(
+            "vlan10=/var/db/vlan10.leases,/etc/vlan10.conf"
+                .parse::<SourceConfig>()
+                .unwrap(),
+            SourceConfig {
+                name: "vlan10".to_owned(),
+                leases: "/var/db/vlan10.leases".into(),
+                config: "/etc/vlan10.conf".into(),
+            }
+        );
+        assert!("vlan10".parse::<SourceConfig>().is_err());
+        assert!("vlan10=/var/db/vlan10.leases"
+            .parse::<SourceConfig>()
+            .is_err());
+        assert!("=/a,/b".parse::<SourceConfig>().is_err());
+    }
+}
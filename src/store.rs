@@ -0,0 +1,1091 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::Ipv4Addr,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, types::Type, Connection, OptionalExtension, Row, Transaction};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    model::{Annotation, Lease, LeaseTime, MacAddr, Sighting},
+    render::{Render, Time, TimeFormat},
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS lease_state (
+        address TEXT NOT NULL,
+        mac TEXT NOT NULL,
+        starts INTEGER,
+        ends INTEGER,
+        cltt INTEGER,
+        hostname TEXT,
+        expired INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS lease_events (
+        id INTEGER PRIMARY KEY,
+        observed_at INTEGER NOT NULL,
+        event TEXT NOT NULL,
+        address TEXT NOT NULL,
+        mac TEXT NOT NULL,
+        starts INTEGER,
+        ends INTEGER,
+        cltt INTEGER,
+        hostname TEXT
+    );
+    CREATE INDEX IF NOT EXISTS lease_events_mac ON lease_events (mac, observed_at);
+    CREATE INDEX IF NOT EXISTS lease_events_address ON lease_events (address, observed_at);
+    CREATE INDEX IF NOT EXISTS lease_events_observed_at ON lease_events (observed_at);
+
+    CREATE TABLE IF NOT EXISTS mac_sightings (
+        mac TEXT PRIMARY KEY,
+        first_seen INTEGER NOT NULL,
+        last_seen INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS annotations (
+        mac TEXT PRIMARY KEY,
+        annotation TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS mac_addresses (
+        mac TEXT NOT NULL,
+        address TEXT NOT NULL,
+        PRIMARY KEY (mac, address)
+    );
+";
+
+/// Changes to the tables in `SCHEMA` made after it was first released, in
+/// order. `PRAGMA user_version` counts how many have been applied.
+const MIGRATIONS: &[&str] = &[
+    "
+    ALTER TABLE lease_state ADD COLUMN source TEXT NOT NULL DEFAULT '';
+    ALTER TABLE lease_events ADD COLUMN source TEXT NOT NULL DEFAULT '';
+    ",
+    "
+    CREATE TABLE webhook_dead_letters (
+        id INTEGER PRIMARY KEY,
+        failed_at INTEGER NOT NULL,
+        webhook TEXT NOT NULL,
+        url TEXT NOT NULL,
+        seq INTEGER NOT NULL,
+        body TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        last_error TEXT NOT NULL
+    );
+    ",
+    "
+    ALTER TABLE webhook_dead_letters ADD COLUMN event TEXT NOT NULL DEFAULT '';
+    ",
+];
+
+/// How many dead letters are kept; older ones are dropped.
+const DEAD_LETTER_LIMIT: i64 = 1000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("sqlite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("store task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+
+    #[error("store lock poisoned")]
+    Poisoned,
+}
+
+/// On-disk state that outlives the dhcpd files, kept in `SQLite`. Queries run
+/// on the blocking thread pool.
+#[derive(Debug, Clone)]
+pub struct Store {
+    conn: Arc<Mutex<Connection>>,
+
+    /// Lease events older than this are pruned on every reload.
+    retention: Option<Duration>,
+}
+
+/// What changed about a lease between two reloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    /// A lease that wasn't in the file before.
+    Started,
+    /// The same lease with a new end or client transaction time.
+    Renewed,
+    Expired,
+    /// The lease is no longer in the file.
+    Removed,
+}
+
+impl EventKind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Started => "started",
+            Self::Renewed => "renewed",
+            Self::Expired => "expired",
+            Self::Removed => "removed",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Self::Started, Self::Renewed, Self::Expired, Self::Removed]
+            .into_iter()
+            .find(|kind| kind.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LeaseEvent {
+    /// Increases with every event recorded; pass it as `before` to page
+    /// back through history.
+    pub id: i64,
+    pub observed_at: Time,
+    pub event: EventKind,
+    pub address: Ipv4Addr,
+    pub hardware_ethernet: MacAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    pub starts: Time,
+    pub ends: Time,
+    pub cltt: Time,
+    /// Empty for events recorded before sources were tracked.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub source: String,
+}
+
+impl Render for LeaseEvent {
+    fn render(&mut self, format: TimeFormat) {
+        self.observed_at.render(format);
+        self.starts.render(format);
+        self.ends.render(format);
+        self.cltt.render(format);
+    }
+}
+
+/// Which events of a MAC's or address's history to read, from the query
+/// string of `/history/mac/:mac` and `/history/ip/:ip`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HistoryQuery {
+    /// Only events older than this [`LeaseEvent::id`].
+    pub before: Option<i64>,
+    /// At most this many, the newest ones.
+    pub limit: Option<usize>,
+    /// Only events from this source, from `?source=`.
+    #[serde(skip)]
+    pub source: Option<String>,
+}
+
+/// A page of history, oldest first.
+#[derive(Debug, Default)]
+pub struct History {
+    pub events: Vec<LeaseEvent>,
+    /// The `before` of the next, older, page, if there is one.
+    pub next_before: Option<i64>,
+}
+
+/// What [`Store::record_leases`] changed.
+#[derive(Debug, Default)]
+pub struct Recorded {
+    /// How many events were logged.
+    pub events: usize,
+    /// The new sightings of MACs that were first seen, seen again later or
+    /// seen at another address.
+    pub sightings: BTreeMap<MacAddr, Sighting>,
+}
+
+/// A webhook delivery that failed every attempt.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetter {
+    /// Assigned when saved.
+    pub id: i64,
+    pub failed_at: Time,
+    pub webhook: String,
+    pub url: String,
+    /// The change's sequence number.
+    pub seq: u64,
+    /// The `X-Dhcpd-Api-Event` header it was sent with.
+    pub event: String,
+    pub body: serde_json::Value,
+    pub attempts: u32,
+    pub last_error: String,
+}
+
+impl Render for DeadLetter {
+    fn render(&mut self, format: TimeFormat) {
+        self.failed_at.render(format);
+    }
+}
+
+/// A lease as last recorded, keyed by address, MAC and start time.
+#[derive(Debug, Clone)]
+struct State {
+    ends: Option<i64>,
+    cltt: Option<i64>,
+    hostname: Option<String>,
+    expired: bool,
+    source: String,
+}
+
+impl State {
+    /// Whether the lease itself changed, ignoring which source it is in.
+    fn changed_from(&self, old: &State) -> bool {
+        (self.ends, self.cltt, &self.hostname, self.expired)
+            != (old.ends, old.cltt, &old.hostname, old.expired)
+    }
+}
+
+type Key = (String, String, Option<i64>);
+
+impl Store {
+    pub fn open<P: AsRef<Path>>(path: P, retention: Option<Duration>) -> Result<Self, Error> {
+        if let Some(dir) = path.as_ref().parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Self::init(Connection::open(path)?, retention)
+    }
+
+    pub fn open_in_memory(retention: Option<Duration>) -> Result<Self, Error> {
+        Self::init(Connection::open_in_memory()?, retention)
+    }
+
+    fn init(mut conn: Connection, retention: Option<Duration>) -> Result<Self, Error> {
+        conn.execute_batch(SCHEMA)?;
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            retention,
+        })
+    }
+
+    /// Run `f` against the connection on the blocking thread pool.
+    async fn run<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, rusqlite::Error> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().map_err(|_| Error::Poisoned)?;
+            Ok(f(&mut conn)?)
+        })
+        .await?
+    }
+
+    /// Compare `leases` with what was recorded last time, log an event for
+    /// every difference, update the MACs' sightings and prune events past
+    /// the retention period.
+    pub async fn record_leases(
+        &self,
+        leases: Arc<Vec<Lease>>,
+        now: DateTime<Utc>,
+    ) -> Result<Recorded, Error> {
+        let retention = self.retention;
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            let events = record_leases(&tx, &leases, now)?;
+            let sightings = record_sightings(&tx, &leases, now)?;
+            // A retention reaching past the earliest time keeps everything.
+            if let Some(cutoff) = retention.and_then(|r| now.checked_sub_signed(r)) {
+                tx.execute(
+                    "DELETE FROM lease_events WHERE observed_at < ?1",
+                    [cutoff.timestamp()],
+                )?;
+            }
+            tx.commit()?;
+            Ok(Recorded { events, sightings })
+        })
+        .await
+    }
+
+    /// Every MAC ever recorded. Read once at startup; after that
+    /// [`Store::record_leases`] returns the ones that change.
+    pub async fn sightings(&self) -> Result<BTreeMap<MacAddr, Sighting>, Error> {
+        self.run(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT s.mac, s.first_seen, s.last_seen, COUNT(a.address)
+                 FROM mac_sightings s LEFT JOIN mac_addresses a USING (mac)
+                 GROUP BY s.mac",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                let mac = parse_column(row, 0)?;
+                let sighting = Sighting {
+                    first_seen: time(row.get(1)?).into(),
+                    last_seen: time(row.get(2)?).into(),
+                    distinct_addresses: row.get(3)?,
+                };
+                Ok((mac, sighting))
+            })?;
+            rows.collect()
+        })
+        .await
+    }
+
+    pub async fn annotations(&self) -> Result<BTreeMap<MacAddr, Annotation>, Error> {
+        self.run(|conn| {
+            let mut stmt = conn.prepare("SELECT mac, annotation FROM annotations")?;
+            let rows = stmt.query_map([], |row| {
+                let annotation: String = row.get(1)?;
+                let annotation = serde_json::from_str(&annotation).map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(1, Type::Text, Box::new(e))
+                })?;
+                Ok((parse_column(row, 0)?, annotation))
+            })?;
+            rows.collect()
+        })
+        .await
+    }
+
+    /// Add or replace annotations, first deleting every existing one if
+    /// `replace` is set.
+    pub async fn save_annotations(
+        &self,
+        annotations: Vec<(MacAddr, Annotation)>,
+        replace: bool,
+    ) -> Result<(), Error> {
+        let annotations = annotations
+            .into_iter()
+            .map(|(mac, annotation)| Ok((mac.to_string(), serde_json::to_string(&annotation)?)))
+            .collect::<Result<Vec<_>, serde_json::Error>>()?;
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            if replace {
+                tx.execute("DELETE FROM annotations", [])?;
+            }
+            {
+                let mut upsert = tx.prepare(
+                    "INSERT INTO annotations (mac, annotation) VALUES (?1, ?2)
+                     ON CONFLICT (mac) DO UPDATE SET annotation = excluded.annotation",
+                )?;
+                for (mac, annotation) in &annotations {
+                    upsert.execute([mac, annotation])?;
+                }
+            }
+            tx.commit()
+        })
+        .await
+    }
+
+    /// Returns whether there was an annotation to delete.
+    pub async fn delete_annotation(&self, mac: &MacAddr) -> Result<bool, Error> {
+        let mac = mac.to_string();
+        self.run(move |conn| {
+            let deleted = conn.execute("DELETE FROM annotations WHERE mac = ?1", [mac])?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+
+    /// Save a dead letter, dropping the oldest past the limit. Returns its
+    /// id.
+    pub async fn save_dead_letter(&self, letter: DeadLetter) -> Result<i64, Error> {
+        let body = serde_json::to_string(&letter.body)?;
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "INSERT INTO webhook_dead_letters
+                     (failed_at, webhook, url, seq, event, body, attempts, last_error)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    letter.failed_at.at().map(|t| t.timestamp()),
+                    letter.webhook,
+                    letter.url,
+                    letter.seq,
+                    letter.event,
+                    body,
+                    letter.attempts,
+                    letter.last_error,
+                ],
+            )?;
+            let id = tx.last_insert_rowid();
+            tx.execute(
+                "DELETE FROM webhook_dead_letters WHERE id <= ?1",
+                [id - DEAD_LETTER_LIMIT],
+            )?;
+            tx.commit()?;
+            Ok(id)
+        })
+        .await
+    }
+
+    /// The newest dead letters first.
+    pub async fn dead_letters(&self, limit: usize) -> Result<Vec<DeadLetter>, Error> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, failed_at, webhook, url, seq, event, body, attempts, last_error
+                 FROM webhook_dead_letters ORDER BY id DESC LIMIT ?1",
+            )?;
+            let rows = stmt.query_map([limit], dead_letter_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    pub async fn dead_letter(&self, id: i64) -> Result<Option<DeadLetter>, Error> {
+        self.run(move |conn| {
+            conn.query_row(
+                "SELECT id, failed_at, webhook, url, seq, event, body, attempts, last_error
+                 FROM webhook_dead_letters WHERE id = ?1",
+                [id],
+                dead_letter_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    /// Returns whether there was a dead letter to delete.
+    pub async fn delete_dead_letter(&self, id: i64) -> Result<bool, Error> {
+        self.run(move |conn| {
+            let deleted = conn.execute("DELETE FROM webhook_dead_letters WHERE id = ?1", [id])?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+
+    /// The last recorded state of every lease that was active at `at`.
+    pub async fn leases_at(&self, at: DateTime<Utc>) -> Result<Vec<Lease>, Error> {
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT address, mac, starts, ends, cltt, hostname, source FROM (
+                     SELECT *, ROW_NUMBER() OVER (
+                         PARTITION BY address, mac, starts ORDER BY id DESC
+                     ) AS n
+                     FROM lease_events WHERE starts <= ?1
+                 )
+                 WHERE n = 1 AND (ends IS NULL OR ends >= ?1)
+                 ORDER BY starts",
+            )?;
+            let rows = stmt.query_map([at.timestamp()], |row| {
+                Ok(Lease {
+                    address: parse_column(row, 0)?,
+                    hardware_ethernet: parse_column(row, 1)?,
+                    starts: row.get::<_, Option<i64>>(2)?.and_then(time),
+                    ends: row.get::<_, Option<i64>>(3)?.and_then(time),
+                    tstp: None,
+                    cltt: row.get::<_, Option<i64>>(4)?.and_then(time),
+                    client_hostname: row.get(5)?,
+                    uid: None,
+                    vendor_class_identifier: None,
+                    source: row.get(6)?,
+                })
+            })?;
+            rows.collect()
+        })
+        .await
+    }
+
+    pub async fn mac_history(&self, mac: &MacAddr, query: HistoryQuery) -> Result<History, Error> {
+        let mac = mac.to_string();
+        self.run(move |conn| events(conn, "mac", &mac, &query))
+            .await
+    }
+
+    pub async fn ip_history(&self, ip: Ipv4Addr, query: HistoryQuery) -> Result<History, Error> {
+        let ip = ip.to_string();
+        self.run(move |conn| events(conn, "address", &ip, &query))
+            .await
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), rusqlite::Error> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+fn record_leases(
+    tx: &Transaction,
+    leases: &[Lease],
+    now: DateTime<Utc>,
+) -> Result<usize, rusqlite::Error> {
+    let mut previous = HashMap::new();
+    {
+        let mut stmt = tx.prepare(
+            "SELECT address, mac, starts, ends, cltt, hostname, expired, source FROM lease_state",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let key: Key = (row.get(0)?, row.get(1)?, row.get(2)?);
+            let state = State {
+                ends: row.get(3)?,
+                cltt: row.get(4)?,
+                hostname: row.get(5)?,
+                expired: row.get(6)?,
+                source: row.get(7)?,
+            };
+            Ok((key, state))
+        })?;
+        for row in rows {
+            let (key, state) = row?;
+            previous.insert(key, state);
+        }
+    }
+
+    let mut insert_event = tx.prepare(
+        "INSERT INTO lease_events
+             (observed_at, event, address, mac, starts, ends, cltt, hostname, source)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    )?;
+    let mut log = |kind: EventKind, key: &Key, state: &State| {
+        insert_event.execute(params![
+            now.timestamp(),
+            kind.name(),
+            key.0,
+            key.1,
+            key.2,
+            state.ends,
+            state.cltt,
+            state.hostname,
+            state.source,
+        ])
+    };
+
+    // dhcpd writes a lease again whenever it changes, and the last block
+    // for a lease is the current one.
+    let key = |lease: &Lease| -> Key {
+        (
+            lease.address.to_string(),
+            lease.hardware_ethernet.to_string(),
+            lease.starts.map(|t| t.timestamp()),
+        )
+    };
+    let mut last = HashMap::new();
+    for (i, lease) in leases.iter().enumerate() {
+        last.insert(key(lease), i);
+    }
+
+    let mut count = 0;
+    let mut current = HashMap::new();
+    for (i, lease) in leases.iter().enumerate() {
+        let key = key(lease);
+        if last.get(&key) != Some(&i) {
+            continue;
+        }
+        let state = State {
+            ends: lease.ends.map(|t| t.timestamp()),
+            cltt: lease.cltt.map(|t| t.timestamp()),
+            hostname: lease.client_hostname.clone(),
+            expired: lease.is_expired(now),
+            source: lease.source.clone(),
+        };
+        let kind = match previous.remove(&key) {
+            None if state.expired => Some(EventKind::Expired),
+            None => Some(EventKind::Started),
+            Some(old) if state.expired && !old.expired => Some(EventKind::Expired),
+            Some(old) if state.changed_from(&old) && !state.expired => Some(EventKind::Renewed),
+            Some(_) => None,
+        };
+        if let Some(kind) = kind {
+            log(kind, &key, &state)?;
+            count += 1;
+        }
+        current.insert(key, state);
+    }
+    for (key, state) in &previous {
+        log(EventKind::Removed, key, state)?;
+        count += 1;
+    }
+
+    tx.execute("DELETE FROM lease_state", [])?;
+    let mut insert_state = tx.prepare(
+        "INSERT INTO lease_state (address, mac, starts, ends, cltt, hostname, expired, source)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?;
+    for (key, state) in &current {
+        insert_state.execute(params![
+            key.0,
+            key.1,
+            key.2,
+            state.ends,
+            state.cltt,
+            state.hostname,
+            state.expired,
+            state.source,
+        ])?;
+    }
+
+    Ok(count)
+}
+
+/// Update the sightings of the MACs in `leases` and return the ones that
+/// changed.
+fn record_sightings(
+    tx: &Transaction,
+    leases: &[Lease],
+    now: DateTime<Utc>,
+) -> Result<BTreeMap<MacAddr, Sighting>, rusqlite::Error> {
+    let mut seen = BTreeMap::<&MacAddr, (i64, i64, Vec<String>)>::new();
+    for lease in leases {
+        let first_seen = lease.starts.or(lease.cltt).unwrap_or(now).timestamp();
+        let last_seen = lease.cltt.max(lease.starts).unwrap_or(now).timestamp();
+        let entry =
+            seen.entry(&lease.hardware_ethernet)
+                .or_insert((first_seen, last_seen, Vec::new()));
+        entry.0 = entry.0.min(first_seen);
+        entry.1 = entry.1.max(last_seen);
+        entry.2.push(lease.address.to_string());
+    }
+
+    let mut select = tx.prepare(
+        "SELECT first_seen, last_seen,
+                (SELECT COUNT(*) FROM mac_addresses WHERE mac = ?1)
+         FROM mac_sightings WHERE mac = ?1",
+    )?;
+    let mut upsert = tx.prepare(
+        "INSERT INTO mac_sightings (mac, first_seen, last_seen) VALUES (?1, ?2, ?3)
+         ON CONFLICT (mac) DO UPDATE SET
+             first_seen = MIN(first_seen, excluded.first_seen),
+             last_seen = MAX(last_seen, excluded.last_seen)",
+    )?;
+    let mut insert_address =
+        tx.prepare("INSERT OR IGNORE INTO mac_addresses (mac, address) VALUES (?1, ?2)")?;
+    let mut sighting = |mac: &str| {
+        select
+            .query_row([mac], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .optional()
+    };
+
+    let mut changed = BTreeMap::new();
+    for (mac, (first_seen, last_seen, addresses)) in seen {
+        let key = mac.to_string();
+        let before: Option<(i64, i64, u32)> = sighting(&key)?;
+        upsert.execute(params![key, first_seen, last_seen])?;
+        for address in addresses {
+            insert_address.execute(params![key, address])?;
+        }
+        let after = sighting(&key)?;
+        if let Some((first_seen, last_seen, distinct_addresses)) = after.filter(|_| after != before)
+        {
+            let sighting = Sighting {
+                first_seen: time(first_seen).into(),
+                last_seen: time(last_seen).into(),
+                distinct_addresses,
+            };
+            changed.insert(mac.clone(), sighting);
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Every event where `column` is `value`, oldest first.
+/// Events are read newest first, so a limit keeps the newest, and returned
+/// oldest first. `id` gives the order they were observed in.
+fn events(
+    conn: &Connection,
+    column: &str,
+    value: &str,
+    query: &HistoryQuery,
+) -> Result<History, rusqlite::Error> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, observed_at, event, address, mac, starts, ends, cltt, hostname, source
+         FROM lease_events
+         WHERE {column} = ?1 AND (?2 IS NULL OR id < ?2) AND (?3 IS NULL OR source = ?3)
+         ORDER BY id DESC LIMIT ?4"
+    ))?;
+    // One more than the limit tells whether there is another page; -1 is no
+    // limit.
+    let limit = query
+        .limit
+        .map_or(-1, |limit| i64::try_from(limit).unwrap_or(i64::MAX - 1) + 1);
+    let rows = stmt.query_map(
+        params![value, query.before, query.source, limit],
+        event_from_row,
+    )?;
+    let mut events = rows.collect::<Result<Vec<_>, _>>()?;
+    let mut next_before = None;
+    if query.limit.is_some_and(|limit| events.len() > limit) {
+        events.pop();
+        next_before = events.last().map(|event| event.id);
+    }
+    events.reverse();
+
+    Ok(History {
+        events,
+        next_before,
+    })
+}
+
+/// Parse a text column with `FromStr`.
+fn parse_column<T>(row: &Row, i: usize) -> Result<T, rusqlite::Error>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let text: String = row.get(i)?;
+    text.parse()
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(i, Type::Text, Box::new(e)))
+}
+
+fn event_from_row(row: &Row) -> Result<LeaseEvent, rusqlite::Error> {
+    let event: String = row.get(2)?;
+
+    Ok(LeaseEvent {
+        id: row.get(0)?,
+        observed_at: time(row.get(1)?).into(),
+        event: EventKind::from_name(&event).ok_or_else(|| {
+            rusqlite::Error::FromSqlConversionFailure(2, Type::Text, "unknown event".into())
+        })?,
+        address: parse_column(row, 3)?,
+        hardware_ethernet: parse_column(row, 4)?,
+        starts: row.get::<_, Option<i64>>(5)?.and_then(time).into(),
+        ends: row.get::<_, Option<i64>>(6)?.and_then(time).into(),
+        cltt: row.get::<_, Option<i64>>(7)?.and_then(time).into(),
+        hostname: row.get(8)?,
+        source: row.get(9)?,
+    })
+}
+
+fn dead_letter_from_row(row: &Row) -> Result<DeadLetter, rusqlite::Error> {
+    let body: String = row.get(6)?;
+    let body = serde_json::from_str(&body)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(6, Type::Text, Box::new(e)))?;
+    Ok(DeadLetter {
+        id: row.get(0)?,
+        failed_at: time(row.get(1)?).into(),
+        webhook: row.get(2)?,
+        url: row.get(3)?,
+        seq: row.get(4)?,
+        event: row.get(5)?,
+        body,
+        attempts: row.get(7)?,
+        last_error: row.get(8)?,
+    })
+}
+
+fn time(timestamp: i64) -> LeaseTime {
+    DateTime::from_timestamp(timestamp, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn lease(d: u8, starts: DateTime<Utc>, hours: i64) -> Lease {
+        Lease::test(d)
+            .with_hostname(&format!("host-{d}"))
+            .with_times(starts, starts + Duration::hours(hours))
+            .with_cltt(starts)
+    }
+
+    #[tokio::test]
+    async fn test_record_transitions() {
+        let store = Store::open_in_memory(None).unwrap();
+        let t0 = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+
+        let first = vec![lease(1, t0, 2), lease(2, t0, 8)];
+        assert_eq!(
+            store
+                .record_leases(Arc::new(first.clone()), t0)
+                .await
+                .unwrap()
+                .events,
+            2
+        );
+        // Nothing changed.
+        assert_eq!(
+            store
+                .record_leases(Arc::new(first), t0)
+                .await
+                .unwrap()
+                .events,
+            0
+        );
+
+        // .1 expires, .2 renews.
+        let t1 = t0 + Duration::hours(4);
+        let mut renewed = lease(2, t0, 12);
+        renewed.cltt = Some(t1);
+        let second = vec![lease(1, t0, 2), renewed];
+        assert_eq!(
+            store
+                .record_leases(Arc::new(second), t1)
+                .await
+                .unwrap()
+                .events,
+            2
+        );
+
+        // The file was compacted and .1 is gone.
+        let t2 = t0 + Duration::hours(5);
+        assert_eq!(
+            store
+                .record_leases(Arc::new(vec![lease(2, t0, 12)]), t2)
+                .await
+                .unwrap()
+                .events,
+            2
+        );
+
+        let history = store
+            .ip_history(Ipv4Addr::new(10, 0, 0, 1), HistoryQuery::default())
+            .await
+            .unwrap()
+            .events;
+        let kinds = history.iter().map(|e| e.event).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![EventKind::Started, EventKind::Expired, EventKind::Removed]
+        );
+        assert_eq!(history[1].observed_at.at(), Some(t1));
+
+        let history = store
+            .mac_history(&MacAddr::from([0, 0, 0, 0, 0, 2]), HistoryQuery::default())
+            .await
+            .unwrap()
+            .events;
+        let kinds = history.iter().map(|e| e.event).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![EventKind::Started, EventKind::Renewed, EventKind::Renewed]
+        );
+        assert_eq!(history[0].hostname.as_deref(), Some("host-2"));
+    }
+
+    #[tokio::test]
+    async fn test_history_pages() {
+        let store = Store::open_in_memory(None).unwrap();
+        let t0 = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+
+        // Five renewals of the same lease.
+        for hour in 0..5 {
+            let mut renewed = lease(1, t0, 8);
+            renewed.cltt = Some(t0 + Duration::hours(hour));
+            let now = t0 + Duration::hours(hour);
+            store
+                .record_leases(Arc::new(vec![renewed]), now)
+                .await
+                .unwrap();
+        }
+        let ip = Ipv4Addr::new(10, 0, 0, 1);
+        let all = store.ip_history(ip, HistoryQuery::default()).await.unwrap();
+        assert_eq!(all.events.len(), 5);
+        assert_eq!(all.next_before, None);
+
+        let mut pages = Vec::new();
+        let mut before = None;
+        loop {
+            let query = HistoryQuery {
+                before,
+                limit: Some(2),
+                ..HistoryQuery::default()
+            };
+            let page = store.ip_history(ip, query).await.unwrap();
+            pages.push(page.events.iter().map(|e| e.id).collect::<Vec<_>>());
+            match page.next_before {
+                Some(next) => before = Some(next),
+                None => break,
+            }
+        }
+        let ids = all.events.iter().map(|e| e.id).collect::<Vec<_>>();
+        assert_eq!(
+            pages,
+            vec![ids[3..].to_vec(), ids[1..3].to_vec(), ids[..1].to_vec()]
+        );
+
+        let other = HistoryQuery {
+            source: Some("lab".to_owned()),
+            ..HistoryQuery::default()
+        };
+        assert!(store.ip_history(ip, other).await.unwrap().events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_repeated_lease_blocks() {
+        let store = Store::open_in_memory(None).unwrap();
+        let t0 = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+
+        // The same lease written twice, the second time renewed.
+        let mut renewed = lease(1, t0, 8);
+        renewed.cltt = Some(t0 + Duration::hours(1));
+        let file = Arc::new(vec![lease(1, t0, 2), renewed, lease(2, t0, 2)]);
+        assert_eq!(
+            store.record_leases(file.clone(), t0).await.unwrap().events,
+            2
+        );
+        assert_eq!(store.record_leases(file, t0).await.unwrap().events, 0);
+
+        let history = store
+            .ip_history(Ipv4Addr::new(10, 0, 0, 1), HistoryQuery::default())
+            .await
+            .unwrap()
+            .events;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].event, EventKind::Started);
+        assert_eq!(history[0].ends.at(), Some(t0 + Duration::hours(8)));
+    }
+
+    #[tokio::test]
+    async fn test_leases_at() {
+        let store = Store::open_in_memory(None).unwrap();
+        let t0 = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+
+        store
+            .record_leases(Arc::new(vec![lease(1, t0, 2)]), t0)
+            .await
+            .unwrap();
+        // Renewed until t0 + 6h.
+        let renewed = lease(1, t0, 6);
+        store
+            .record_leases(Arc::new(vec![renewed]), t0 + Duration::hours(1))
+            .await
+            .unwrap();
+        // Compacted away, then a new lease for the address.
+        let t1 = t0 + Duration::days(1);
+        store
+            .record_leases(Arc::new(vec![lease(2, t1, 2)]), t1)
+            .await
+            .unwrap();
+
+        let mut found = Vec::new();
+        for hours in [-1, 4, 7, 25] {
+            let leases = store.leases_at(t0 + Duration::hours(hours)).await.unwrap();
+            found.push(
+                leases
+                    .iter()
+                    .map(|l| l.address.octets()[3])
+                    .collect::<Vec<_>>(),
+            );
+        }
+        assert_eq!(found, vec![vec![], vec![1], vec![], vec![2]]);
+    }
+
+    #[tokio::test]
+    async fn test_sightings() {
+        let store = Store::open_in_memory(None).unwrap();
+        let t0 = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let t1 = t0 + Duration::days(3);
+
+        let mac = MacAddr::from([0, 0, 0, 0, 0, 1]);
+        let mut moved = lease(7, t1, 2);
+        moved.hardware_ethernet = mac.clone();
+        let first = Arc::new(vec![lease(1, t0, 2)]);
+        let recorded = store.record_leases(first.clone(), t0).await.unwrap();
+        assert_eq!(recorded.sightings[&mac].distinct_addresses, 1);
+        // Nothing new about the MAC, so nothing to report.
+        let recorded = store.record_leases(first, t0).await.unwrap();
+        assert!(recorded.sightings.is_empty());
+        // A later reload that no longer has the first lease.
+        let recorded = store
+            .record_leases(Arc::new(vec![moved]), t1)
+            .await
+            .unwrap();
+
+        let expected = Sighting {
+            first_seen: Some(t0).into(),
+            last_seen: Some(t1).into(),
+            distinct_addresses: 2,
+        };
+        assert_eq!(recorded.sightings[&mac], expected);
+        assert_eq!(store.sightings().await.unwrap()[&mac], expected);
+    }
+
+    #[tokio::test]
+    async fn test_annotations() {
+        let store = Store::open_in_memory(None).unwrap();
+        let phone = MacAddr::from([0x02, 0, 0, 0, 0, 1]);
+        let tv = MacAddr::from([0x10, 0x20, 0x30, 0, 0, 2]);
+        let annotation = Annotation {
+            name: Some("Dylan's phone".to_string()),
+            tags: vec!["family".to_string()],
+            ..Annotation::default()
+        };
+
+        store
+            .save_annotations(vec![(phone.clone(), annotation.clone())], false)
+            .await
+            .unwrap();
+        store
+            .save_annotations(vec![(tv.clone(), Annotation::default())], false)
+            .await
+            .unwrap();
+        assert_eq!(store.annotations().await.unwrap().len(), 2);
+        assert_eq!(store.annotations().await.unwrap()[&phone], annotation);
+
+        assert!(store.delete_annotation(&tv).await.unwrap());
+        assert!(!store.delete_annotation(&tv).await.unwrap());
+
+        store
+            .save_annotations(vec![(tv.clone(), Annotation::default())], true)
+            .await
+            .unwrap();
+        let annotations = store.annotations().await.unwrap();
+        assert_eq!(annotations.keys().collect::<Vec<_>>(), vec![&tv]);
+    }
+
+    #[tokio::test]
+    async fn test_retention() {
+        let store = Store::open_in_memory(Some(Duration::days(7))).unwrap();
+        let t0 = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+
+        store
+            .record_leases(Arc::new(vec![lease(1, t0, 2)]), t0)
+            .await
+            .unwrap();
+        store
+            .record_leases(Arc::new(vec![lease(2, t0, 2)]), t0 + Duration::days(8))
+            .await
+            .unwrap();
+
+        let history = store
+            .ip_history(Ipv4Addr::new(10, 0, 0, 1), HistoryQuery::default())
+            .await
+            .unwrap()
+            .events;
+        let kinds = history.iter().map(|e| e.event).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![EventKind::Removed]);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_before_sources() {
+        let t0 = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        conn.execute(
+            "INSERT INTO lease_state (address, mac, starts, ends, cltt, hostname, expired)
+             VALUES ('10.0.0.1', '00:00:00:00:00:01', ?1, ?2, ?1, 'host-1', 0)",
+            [t0.timestamp(), (t0 + Duration::hours(2)).timestamp()],
+        )
+        .unwrap();
+        let store = Store::init(conn, None).unwrap();
+
+        // Only the source is new, which isn't a change to the lease.
+        let count = store
+            .record_leases(Arc::new(vec![lease(1, t0, 2)]), t0)
+            .await
+            .unwrap()
+            .events;
+        assert_eq!(count, 0);
+
+        let count = store
+            .record_leases(Arc::new(Vec::new()), t0 + Duration::hours(1))
+            .await
+            .unwrap()
+            .events;
+        assert_eq!(count, 1);
+        let history = store
+            .ip_history(Ipv4Addr::new(10, 0, 0, 1), HistoryQuery::default())
+            .await
+            .unwrap()
+            .events;
+        assert_eq!(history[0].source, "lan");
+    }
+}
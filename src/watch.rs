@@ -0,0 +1,268 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher, WatcherKind};
+use serde::Serialize;
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver},
+    time::Instant,
+};
+
+use crate::render::{Render, Time, TimeFormat};
+
+/// How long a file has to be quiet before it is reloaded. dhcpd writes the
+/// leases file in several steps.
+const QUIET: Duration = Duration::from_millis(200);
+
+/// Reload at least this often while a file keeps changing.
+const MAX_DELAY: Duration = Duration::from_millis(800);
+
+/// How changes to the dhcpd files are noticed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchMode {
+    /// Not watching yet.
+    #[default]
+    Starting,
+    /// Filesystem events (inotify, Windows or macOS file system events).
+    Events,
+    /// Checking modification times on an interval. Events that can't be
+    /// relied on, such as kqueue's, may still trigger reloads sooner.
+    Polling,
+}
+
+/// Which version of a file is on disk. dhcpd replaces the leases file by
+/// renaming a new one over it, which changes the inode, and the new file can
+/// have an older mtime than our last reload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileStamp {
+    /// `None` on platforms without inode numbers.
+    pub inode: Option<u64>,
+    pub size: u64,
+    pub modified: Time,
+}
+
+/// How a file differs from an earlier [`FileStamp`] of the same path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileChange {
+    Unchanged,
+    /// Written in place.
+    Modified,
+    /// A different file was moved into place.
+    Replaced,
+    /// Written in place and now shorter.
+    Truncated,
+}
+
+impl Render for FileStamp {
+    fn render(&mut self, format: TimeFormat) {
+        self.modified.render(format);
+    }
+}
+
+impl FileStamp {
+    pub fn new(metadata: &std::fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let inode = Some(std::os::unix::fs::MetadataExt::ino(metadata));
+        #[cfg(not(unix))]
+        let inode = None;
+
+        Self {
+            inode,
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(DateTime::<Utc>::from).into(),
+        }
+    }
+
+    pub async fn of(path: &Path) -> std::io::Result<Self> {
+        Ok(Self::new(&tokio::fs::metadata(path).await?))
+    }
+
+    pub fn change_from(&self, earlier: &FileStamp) -> FileChange {
+        if self == earlier {
+            FileChange::Unchanged
+        } else if self.inode != earlier.inode {
+            FileChange::Replaced
+        } else if self.size < earlier.size {
+            FileChange::Truncated
+        } else {
+            FileChange::Modified
+        }
+    }
+}
+
+/// Filesystem events for a set of files, batched into bursts.
+pub struct FileWatcher {
+    // Dropping the watcher stops the events.
+    watcher: RecommendedWatcher,
+    events: UnboundedReceiver<PathBuf>,
+}
+
+impl FileWatcher {
+    /// Whether events alone catch every change. kqueue watches the file that
+    /// was opened, so it misses changes to a file replaced by a rename until
+    /// it is watched again, and anything in between.
+    pub fn reliable() -> bool {
+        RecommendedWatcher::kind() != WatcherKind::Kqueue
+    }
+
+    /// Watch `files`, which must be canonical paths. The parent directories
+    /// are watched so that files replaced by a rename are seen, and the files
+    /// themselves for backends that only report new names in a directory.
+    pub fn new(files: &[&Path]) -> Result<Self, notify::Error> {
+        let (tx, events) = mpsc::unbounded_channel();
+        let wanted = files
+            .iter()
+            .map(|f| f.to_path_buf())
+            .collect::<BTreeSet<_>>();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        tracing::warn!("file watch error: {}", e);
+                        return;
+                    }
+                };
+                if matches!(event.kind, EventKind::Access(_) | EventKind::Remove(_)) {
+                    return;
+                }
+                for path in event.paths {
+                    if wanted.contains(&path) {
+                        // The receiver is only gone during shutdown.
+                        let _ = tx.send(path);
+                    }
+                }
+            })?;
+
+        let dirs = files
+            .iter()
+            .filter_map(|f| f.parent())
+            .collect::<BTreeSet<_>>();
+        for dir in dirs {
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+        for file in files {
+            watcher.watch(file, RecursiveMode::NonRecursive)?;
+        }
+
+        Ok(Self { watcher, events })
+    }
+
+    /// Watch `path` again, which follows it to a file renamed into place.
+    fn rewatch(&mut self, path: &Path) {
+        // The old file may be gone along with its watch.
+        let _ = self.watcher.unwatch(path);
+        if let Err(e) = self.watcher.watch(path, RecursiveMode::NonRecursive) {
+            tracing::warn!("Failed to watch {}: {}", path.display(), e);
+        }
+    }
+
+    /// Wait for a burst of changes to settle and return the files that
+    /// changed. `None` if the watcher stopped.
+    pub async fn changed(&mut self) -> Option<BTreeSet<PathBuf>> {
+        let first = self.events.recv().await?;
+        let mut changed = BTreeSet::from([first]);
+        let deadline = Instant::now() + MAX_DELAY;
+        loop {
+            let wait = (Instant::now() + QUIET).min(deadline);
+            match tokio::time::timeout_at(wait, self.events.recv()).await {
+                Ok(Some(path)) => {
+                    changed.insert(path);
+                }
+                Ok(None) | Err(_) => break,
+            }
+        }
+        for path in &changed {
+            self.rewatch(path);
+        }
+
+        Some(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_file_change() {
+        let stamp = FileStamp {
+            inode: Some(7),
+            size: 100,
+            modified: Utc::now().into(),
+        };
+        let older = FileStamp {
+            modified: stamp
+                .modified
+                .at()
+                .map(|t| t - chrono::Duration::hours(1))
+                .into(),
+            ..stamp.clone()
+        };
+
+        assert_eq!(stamp.change_from(&stamp), FileChange::Unchanged);
+        assert_eq!(older.change_from(&stamp), FileChange::Modified);
+        assert_eq!(
+            FileStamp {
+                size: 10,
+                ..stamp.clone()
+            }
+            .change_from(&stamp),
+            FileChange::Truncated
+        );
+        // A replacement is noticed even with an older mtime.
+        assert_eq!(
+            FileStamp {
+                inode: Some(8),
+                ..older
+            }
+            .change_from(&stamp),
+            FileChange::Replaced
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rename_into_place() {
+        let dir = std::env::temp_dir().join(format!("dhcpd-api-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = std::fs::canonicalize(&dir).unwrap();
+        let leases = dir.join("dhcpd.leases");
+        let other = dir.join("other");
+        std::fs::write(&leases, "").unwrap();
+
+        let mut watcher = FileWatcher::new(&[&leases]).unwrap();
+        std::fs::write(&other, "ignored").unwrap();
+        let new = dir.join("dhcpd.leases.new");
+        std::fs::write(&new, "lease").unwrap();
+        std::fs::rename(&new, &leases).unwrap();
+
+        let changed = tokio::time::timeout(Duration::from_secs(5), watcher.changed())
+            .await
+            .expect("Change seen")
+            .unwrap();
+        assert_eq!(changed, BTreeSet::from([leases.clone()]));
+
+        // Appending to the file that was moved into place.
+        std::fs::File::options()
+            .append(true)
+            .open(&leases)
+            .unwrap()
+            .write_all(b" 10.0.0.1 {}")
+            .unwrap();
+        let changed = tokio::time::timeout(Duration::from_secs(5), watcher.changed())
+            .await
+            .expect("Append seen")
+            .unwrap();
+        assert_eq!(changed, BTreeSet::from([leases]));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
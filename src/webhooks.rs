@@ -0,0 +1,776 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::{header::CONTENT_TYPE, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::Sha256;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use crate::{
+    changes::{self, Change, ChangeFilter, FilterConfig},
+    db::DB,
+    render::{Render, Time, TimeFormat},
+    source::SourceNames,
+    store::{DeadLetter, Store},
+};
+
+/// How many deliveries `/webhooks/deliveries` shows.
+const RECENT_DELIVERIES: usize = 200;
+
+/// How long to wait for a webhook to respond.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const ATTEMPTS: u32 = 5;
+
+/// How many deliveries can wait for each webhook. Past that they go straight
+/// to the dead-letter queue.
+const QUEUE_LIMIT: usize = 1000;
+
+/// Wait before the first retry, doubled after each one.
+const BACKOFF: Duration = Duration::from_secs(1);
+
+pub const SIGNATURE_HEADER: &str = "x-dhcpd-api-signature";
+const EVENT_HEADER: &str = "x-dhcpd-api-event";
+const DELIVERY_HEADER: &str = "x-dhcpd-api-delivery";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("can't read {0}: {1}")]
+    Read(String, std::io::Error),
+
+    #[error("invalid webhooks file: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("webhook {0}: invalid URL: {1}")]
+    InvalidUrl(String, String),
+
+    #[error("webhook {0}: {1}")]
+    InvalidFilter(String, changes::Error),
+
+    #[error("http client error: {0}")]
+    Client(#[from] reqwest::Error),
+}
+
+/// One webhook, as given in the `--webhooks` file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub name: String,
+    pub url: String,
+    /// Which changes to send. Every change if absent.
+    #[serde(default)]
+    pub filter: FilterConfig,
+    /// The JSON body, with `{{field}}` placeholders for the change's fields
+    /// and `{{change}}` for all of it. The change itself if absent.
+    pub template: Option<Value>,
+    /// Signs the body with HMAC-SHA256, sent as `sha256=<hex>` in the
+    /// `X-Dhcpd-Api-Signature` header.
+    pub secret: Option<String>,
+}
+
+impl WebhookConfig {
+    /// Reads a JSON array of webhooks.
+    pub fn load(path: &Path) -> Result<Vec<Self>, Error> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| Error::Read(path.display().to_string(), e))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+struct Target {
+    config: WebhookConfig,
+    url: Url,
+    filter: ChangeFilter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    /// Being sent, or waiting to be retried.
+    Pending,
+    Delivered,
+    /// Every attempt failed, or it couldn't be queued; the body is in the
+    /// dead-letter queue.
+    Failed,
+}
+
+/// One change sent to one webhook.
+#[derive(Debug, Clone, Serialize)]
+pub struct Delivery {
+    pub id: u64,
+    pub webhook: String,
+    pub seq: u64,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    /// The HTTP status of the last response.
+    pub response_status: Option<u16>,
+    pub last_error: Option<String>,
+    pub created_at: Time,
+    pub updated_at: Time,
+}
+
+impl Render for Delivery {
+    fn render(&mut self, format: TimeFormat) {
+        self.created_at.render(format);
+        self.updated_at.render(format);
+    }
+}
+
+/// Sends device changes to the configured webhooks.
+#[derive(Clone)]
+pub struct Webhooks {
+    targets: Arc<[Target]>,
+    /// A queue per target, in the same order, each emptied by one worker so
+    /// a webhook gets its changes one at a time and in order.
+    queues: Arc<[mpsc::Sender<Outgoing>]>,
+    /// The other ends of `queues`, until `run` starts their workers.
+    workers: Arc<Mutex<Vec<mpsc::Receiver<Outgoing>>>>,
+    client: reqwest::Client,
+    store: Store,
+    attempts: u32,
+    backoff: Duration,
+    recent: Arc<Mutex<VecDeque<Delivery>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Webhooks {
+    pub fn new(
+        configs: Vec<WebhookConfig>,
+        names: &SourceNames,
+        store: Store,
+    ) -> Result<Self, Error> {
+        let targets = configs
+            .into_iter()
+            .map(|config| {
+                let url = Url::parse(&config.url)
+                    .map_err(|e| Error::InvalidUrl(config.name.clone(), e.to_string()))?;
+                let filter = config
+                    .filter
+                    .clone()
+                    .build(names)
+                    .map_err(|e| Error::InvalidFilter(config.name.clone(), e))?;
+                Ok(Target {
+                    config,
+                    url,
+                    filter,
+                })
+            })
+            .collect::<Result<Arc<[_]>, Error>>()?;
+        let (queues, workers): (Vec<_>, _) =
+            targets.iter().map(|_| mpsc::channel(QUEUE_LIMIT)).unzip();
+
+        Ok(Self {
+            targets,
+            queues: queues.into(),
+            workers: Arc::new(Mutex::new(workers)),
+            client: reqwest::Client::builder().timeout(TIMEOUT).build()?,
+            store,
+            attempts: ATTEMPTS,
+            backoff: BACKOFF,
+            recent: Arc::default(),
+            next_id: Arc::new(AtomicU64::new(1)),
+        })
+    }
+
+    /// Use `attempts` tries per delivery, waiting `backoff` before the first
+    /// retry.
+    #[cfg(test)]
+    fn with_retries(mut self, attempts: u32, backoff: Duration) -> Self {
+        self.attempts = attempts;
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    /// The most recent deliveries, newest first.
+    pub fn deliveries(&self) -> Vec<Delivery> {
+        self.recent().iter().rev().cloned().collect()
+    }
+
+    pub async fn dead_letters(&self, limit: usize) -> Result<Vec<DeadLetter>, crate::store::Error> {
+        self.store.dead_letters(limit).await
+    }
+
+    /// Take dead letter `id` out of the dead-letter queue and send it again,
+    /// to the webhook's current URL and after anything already queued for
+    /// it. `None` if there is no such letter or its webhook is gone.
+    pub async fn redeliver(
+        &self,
+        id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Delivery>, crate::store::Error> {
+        let Some(letter) = self.store.dead_letter(id).await? else {
+            return Ok(None);
+        };
+        let Some(i) = self
+            .targets
+            .iter()
+            .position(|target| target.config.name == letter.webhook)
+        else {
+            return Ok(None);
+        };
+        // Someone else may have redelivered it first.
+        if !self.store.delete_dead_letter(id).await? {
+            return Ok(None);
+        }
+
+        let target = &self.targets[i];
+        let delivery = Outgoing {
+            id: self.track(target, letter.seq, now),
+            webhook: letter.webhook,
+            url: target.url.clone(),
+            seq: letter.seq,
+            event: letter.event,
+            body: letter.body,
+            secret: target.config.secret.clone(),
+        };
+        let id = delivery.id;
+        if let Some(delivery) = self.send(i, delivery) {
+            let error = "queue full".to_owned();
+            self.fail(delivery, 0, None, error, now).await;
+        }
+        Ok(self.recent().iter().rev().find(|d| d.id == id).cloned())
+    }
+
+    /// Queue a delivery for every webhook each new change matches, until
+    /// shutdown. Deliveries run on `tracker`, one webhook's at a time. Changes
+    /// are watched for from when this is called, not from when the future is
+    /// first polled.
+    pub fn run(
+        self,
+        db: DB,
+        shutdown: CancellationToken,
+        tracker: TaskTracker,
+    ) -> impl Future<Output = ()> {
+        let workers =
+            std::mem::take(&mut *self.workers.lock().unwrap_or_else(PoisonError::into_inner));
+        for queue in workers {
+            tracker.spawn(self.clone().work(queue, db.clone(), shutdown.clone()));
+        }
+
+        let mut journaled = db.subscribe();
+        let journal = db.load().journal.clone();
+        let mut cursor = journal.cursor(journal.last_seq());
+        async move {
+            loop {
+                tokio::select! {
+                    changed = journaled.changed() => if changed.is_err() { return },
+                    () = shutdown.cancelled() => return,
+                }
+
+                let snapshot = db.load();
+                let journal = &snapshot.journal;
+                if journal.missed(&cursor) {
+                    tracing::warn!(
+                        "webhooks fell behind; changes after {} were dropped",
+                        cursor
+                    );
+                }
+                for change in journal.since(&cursor) {
+                    for (i, target) in self.targets.iter().enumerate() {
+                        if target.filter.matches(change, &snapshot.vendor_mapping) {
+                            let delivery = self.queue(target, change, snapshot.now());
+                            if let Some(delivery) = self.send(i, delivery) {
+                                let error = "queue full".to_owned();
+                                let now = snapshot.now();
+                                let webhooks = self.clone();
+                                tracker.spawn(async move {
+                                    webhooks.fail(delivery, 0, None, error, now).await;
+                                });
+                            }
+                        }
+                    }
+                }
+                cursor = journal.cursor(journal.last_seq());
+            }
+        }
+    }
+
+    /// Send what arrives on `queue` one at a time. At shutdown whatever is
+    /// still waiting goes to the dead-letter queue, to be redelivered later.
+    async fn work(self, mut queue: mpsc::Receiver<Outgoing>, db: DB, shutdown: CancellationToken) {
+        loop {
+            let delivery = tokio::select! {
+                delivery = queue.recv() => delivery,
+                () = shutdown.cancelled() => break,
+            };
+            let Some(delivery) = delivery else {
+                return;
+            };
+            self.deliver(delivery, &db, &shutdown).await;
+        }
+
+        queue.close();
+        while let Some(delivery) = queue.recv().await {
+            let error = "shut down before sending".to_owned();
+            self.fail(delivery, 0, None, error, db.load().now()).await;
+        }
+    }
+
+    /// Hands `delivery` to target `i`'s worker, or gives it back if the
+    /// queue is full.
+    fn send(&self, i: usize, delivery: Outgoing) -> Option<Outgoing> {
+        match self.queues[i].try_send(delivery) {
+            Ok(()) => None,
+            Err(TrySendError::Full(delivery) | TrySendError::Closed(delivery)) => Some(delivery),
+        }
+    }
+
+    /// Add a pending delivery to the recent ones and return its id.
+    fn track(&self, target: &Target, seq: u64, now: DateTime<Utc>) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut recent = self.recent();
+        recent.push_back(Delivery {
+            id,
+            webhook: target.config.name.clone(),
+            seq,
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            response_status: None,
+            last_error: None,
+            created_at: now.into(),
+            updated_at: now.into(),
+        });
+        while recent.len() > RECENT_DELIVERIES {
+            recent.pop_front();
+        }
+        id
+    }
+
+    fn queue(&self, target: &Target, change: &Change, now: DateTime<Utc>) -> Outgoing {
+        Outgoing {
+            id: self.track(target, change.seq, now),
+            webhook: target.config.name.clone(),
+            url: target.url.clone(),
+            seq: change.seq,
+            event: change.kind.action().name().to_owned(),
+            body: render_body(target.config.template.as_ref(), change),
+            secret: target.config.secret.clone(),
+        }
+    }
+
+    /// Send `delivery`, retrying with backoff, and put it in the dead-letter
+    /// queue if every attempt fails or the server shuts down first.
+    async fn deliver(&self, delivery: Outgoing, db: &DB, shutdown: &CancellationToken) {
+        let body = delivery.body.to_string();
+        let signature = delivery.secret.as_deref().map(|secret| sign(secret, &body));
+        let mut backoff = self.backoff;
+        let mut last_error = String::new();
+        let mut last_status = None;
+        let mut attempts = 0;
+        while attempts < self.attempts {
+            if attempts > 0 {
+                tokio::select! {
+                    () = tokio::time::sleep(backoff) => backoff *= 2,
+                    () = shutdown.cancelled() => {
+                        last_error = format!("shut down before retrying: {last_error}");
+                        break;
+                    }
+                }
+            }
+            attempts += 1;
+
+            let mut request = self
+                .client
+                .post(delivery.url.clone())
+                .header(CONTENT_TYPE, "application/json")
+                .header(EVENT_HEADER, &delivery.event)
+                .header(DELIVERY_HEADER, delivery.id.to_string())
+                .body(body.clone());
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+            let (status, error) = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    let status = Some(response.status().as_u16());
+                    self.update(
+                        delivery.id,
+                        DeliveryStatus::Delivered,
+                        attempts,
+                        status,
+                        None,
+                        db.load().now(),
+                    );
+                    return;
+                }
+                Ok(response) => (
+                    Some(response.status().as_u16()),
+                    format!("HTTP {}", response.status()),
+                ),
+                Err(e) => (None, e.to_string()),
+            };
+            tracing::debug!(
+                "webhook {} delivery {} attempt {} failed: {}",
+                delivery.webhook,
+                delivery.id,
+                attempts,
+                error
+            );
+            self.update(
+                delivery.id,
+                DeliveryStatus::Pending,
+                attempts,
+                status,
+                Some(error.clone()),
+                db.load().now(),
+            );
+            (last_status, last_error) = (status, error);
+        }
+
+        self.fail(delivery, attempts, last_status, last_error, db.load().now())
+            .await;
+    }
+
+    /// Give up on `delivery` and put it in the dead-letter queue.
+    async fn fail(
+        &self,
+        delivery: Outgoing,
+        attempts: u32,
+        last_status: Option<u16>,
+        last_error: String,
+        now: DateTime<Utc>,
+    ) {
+        tracing::warn!(
+            "webhook {} delivery {} failed: {}",
+            delivery.webhook,
+            delivery.id,
+            last_error
+        );
+        let id = delivery.id;
+        let letter = DeadLetter {
+            id: 0,
+            failed_at: now.into(),
+            webhook: delivery.webhook,
+            url: delivery.url.to_string(),
+            seq: delivery.seq,
+            event: delivery.event,
+            body: delivery.body,
+            attempts,
+            last_error: last_error.clone(),
+        };
+        if let Err(e) = self.store.save_dead_letter(letter).await {
+            tracing::error!("can't save dead letter: {}", e);
+        }
+        self.update(
+            id,
+            DeliveryStatus::Failed,
+            attempts,
+            last_status,
+            Some(last_error),
+            now,
+        );
+    }
+
+    fn update(
+        &self,
+        id: u64,
+        status: DeliveryStatus,
+        attempts: u32,
+        response_status: Option<u16>,
+        last_error: Option<String>,
+        now: DateTime<Utc>,
+    ) {
+        let mut recent = self.recent();
+        if let Some(delivery) = recent.iter_mut().rev().find(|d| d.id == id) {
+            delivery.status = status;
+            delivery.attempts = attempts;
+            delivery.response_status = response_status;
+            delivery.last_error = last_error;
+            delivery.updated_at = now.into();
+        }
+    }
+
+    fn recent(&self) -> std::sync::MutexGuard<'_, VecDeque<Delivery>> {
+        self.recent.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A delivery's request, made when it is queued.
+struct Outgoing {
+    id: u64,
+    webhook: String,
+    url: Url,
+    seq: u64,
+    event: String,
+    body: Value,
+    secret: Option<String>,
+}
+
+/// `sha256=` and the hex HMAC-SHA256 of `body`.
+pub fn sign(secret: &str, body: &str) -> String {
+    // HMAC takes keys of any length.
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .unwrap_or_else(|_| unreachable!("HMAC accepts any key length"));
+    mac.update(body.as_bytes());
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+/// The body for `change`, from `template` if there is one.
+fn render_body(template: Option<&Value>, change: &Change) -> Value {
+    let Ok(Value::Object(mut fields)) = serde_json::to_value(change) else {
+        return Value::Null;
+    };
+    let Some(template) = template else {
+        return Value::Object(fields);
+    };
+    fields.insert("action".to_owned(), change.kind.action().name().into());
+    fields.insert("change".to_owned(), Value::Object(fields.clone()));
+    render(template, &fields)
+}
+
+/// Fill in `{{field}}` placeholders in the strings in `template`. A string
+/// that is just one placeholder becomes the field's value, whatever its type.
+fn render(template: &Value, fields: &Map<String, Value>) -> Value {
+    match template {
+        Value::String(text) => {
+            let whole = text
+                .strip_prefix("{{")
+                .and_then(|rest| rest.strip_suffix("}}"))
+                .filter(|name| !name.contains("{{"));
+            if let Some(name) = whole {
+                return fields.get(name.trim()).cloned().unwrap_or(Value::Null);
+            }
+            Value::String(interpolate(text, fields))
+        }
+        Value::Array(items) => items.iter().map(|item| render(item, fields)).collect(),
+        Value::Object(object) => object
+            .iter()
+            .map(|(key, value)| (key.clone(), render(value, fields)))
+            .collect(),
+        other => other.clone(),
+    }
+}
+
+fn interpolate(mut text: &str, fields: &Map<String, Value>) -> String {
+    let mut out = String::new();
+    while let Some(start) = text.find("{{") {
+        let Some(len) = text[start..].find("}}") else {
+            break;
+        };
+        out.push_str(&text[..start]);
+        match fields.get(text[start + 2..start + len].trim()) {
+            Some(Value::String(value)) => out.push_str(value),
+            Some(Value::Null) | None => (),
+            Some(value) => out.push_str(&value.to_string()),
+        }
+        text = &text[start + len + 2..];
+    }
+    out.push_str(text);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use std::sync::atomic::AtomicU32;
+
+    use axum::{extract::State, http::HeaderMap, http::StatusCode, routing::post, Router};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        db::{self, Database},
+        model::Lease,
+    };
+
+    /// A local webhook receiver that fails its first `failures` requests.
+    #[derive(Clone, Default)]
+    struct Stub {
+        requests: Arc<Mutex<Vec<(HeaderMap, String)>>>,
+        failures: Arc<AtomicU32>,
+    }
+
+    async fn receive(State(stub): State<Stub>, headers: HeaderMap, body: String) -> StatusCode {
+        stub.requests.lock().unwrap().push((headers, body));
+        let failing = stub
+            .failures
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok();
+        if failing {
+            StatusCode::INTERNAL_SERVER_ERROR
+        } else {
+            StatusCode::NO_CONTENT
+        }
+    }
+
+    async fn stub(failures: u32) -> (String, Stub) {
+        let stub = Stub {
+            failures: Arc::new(AtomicU32::new(failures)),
+            ..Stub::default()
+        };
+        let app = Router::new()
+            .route("/hook", post(receive))
+            .with_state(stub.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, stub)
+    }
+
+    /// Send the changes from renaming .1 and adding .2 to a webhook made from
+    /// `config`, and return its `count` deliveries, oldest first, once they
+    /// are done.
+    async fn deliver(config: Value, attempts: u32, count: usize) -> (Webhooks, DB, Vec<Delivery>) {
+        let db = DB::new(Database::in_memory(&["lan"]));
+        let lease = |d, hostname| Lease::test(d).active_at(Utc::now()).with_hostname(hostname);
+        db::update_leases(&db, "lan", vec![lease(1, "one")])
+            .await
+            .unwrap();
+        let names = SourceNames(Arc::from(["lan".to_owned()]));
+        let config = serde_json::from_value(config).unwrap();
+        let webhooks = Webhooks::new(vec![config], &names, db.load().store.clone())
+            .unwrap()
+            .with_retries(attempts, Duration::from_millis(10));
+        let tracker = TaskTracker::new();
+        tracker.spawn(
+            webhooks
+                .clone()
+                .run(db.clone(), CancellationToken::new(), tracker.clone()),
+        );
+
+        db::update_leases(&db, "lan", vec![lease(1, "uno"), lease(2, "two")])
+            .await
+            .unwrap();
+        let deliveries = done(&webhooks, count).await;
+        (webhooks, db, deliveries)
+    }
+
+    /// Wait for `webhooks` to have made `count` deliveries and finished them.
+    async fn done(webhooks: &Webhooks, count: usize) -> Vec<Delivery> {
+        let done = async {
+            loop {
+                let mut deliveries = webhooks.deliveries();
+                if deliveries.len() == count
+                    && deliveries
+                        .iter()
+                        .all(|d| d.status != DeliveryStatus::Pending)
+                {
+                    deliveries.reverse();
+                    return deliveries;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), done)
+            .await
+            .expect("delivered")
+    }
+
+    #[tokio::test]
+    async fn test_signed_delivery_with_retry() {
+        let (url, stub) = stub(1).await;
+        let config = json!({
+            "name": "chat",
+            "url": url,
+            "filter": {"events": ["changed"]},
+            "template": {
+                "text": "{{hardware_ethernet}} is now {{to}}",
+                "seq": "{{seq}}",
+                "change": "{{change}}",
+            },
+            "secret": "hunter2",
+        });
+        let (_, _, deliveries) = deliver(config, 3, 1).await;
+        let delivery = &deliveries[0];
+        assert_eq!(delivery.status, DeliveryStatus::Delivered);
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(delivery.response_status, Some(204));
+
+        let requests = stub.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let (headers, body) = &requests[1];
+        assert_eq!(headers[SIGNATURE_HEADER], sign("hunter2", body));
+        assert_eq!(headers[EVENT_HEADER], "changed");
+        let body: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["text"], "00:00:00:00:00:01 is now uno");
+        assert_eq!(body["seq"], 1);
+        assert_eq!(body["change"]["event"], "hostname_changed");
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter() {
+        let (url, stub) = stub(u32::MAX).await;
+        let config = json!({
+            "name": "down",
+            "url": url,
+            "filter": {"macs": ["00:00:00:00:00:02"]},
+        });
+        let (webhooks, db, deliveries) = deliver(config, 2, 1).await;
+        let delivery = &deliveries[0];
+        assert_eq!(delivery.status, DeliveryStatus::Failed);
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(delivery.response_status, Some(500));
+        assert_eq!(stub.requests.lock().unwrap().len(), 2);
+
+        let letters = webhooks.dead_letters(10).await.unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].webhook, "down");
+        assert_eq!(letters[0].attempts, 2);
+        assert_eq!(letters[0].event, "added");
+        assert_eq!(letters[0].body["event"], "device_added");
+        assert!(letters[0].last_error.contains("500"));
+
+        // Once the webhook is back, the letter can be sent again.
+        stub.failures.store(0, Ordering::Relaxed);
+        let now = db.load().now();
+        let redelivery = webhooks.redeliver(letters[0].id, now).await.unwrap();
+        assert_eq!(redelivery.unwrap().seq, delivery.seq);
+        assert!(webhooks
+            .redeliver(letters[0].id, now)
+            .await
+            .unwrap()
+            .is_none());
+        let deliveries = done(&webhooks, 2).await;
+        assert_eq!(deliveries[1].status, DeliveryStatus::Delivered);
+        assert!(webhooks.dead_letters(10).await.unwrap().is_empty());
+
+        let requests = stub.requests.lock().unwrap();
+        let (headers, body) = &requests[2];
+        assert_eq!(headers[EVENT_HEADER], "added");
+        assert_eq!(body, &requests[0].1);
+    }
+
+    #[tokio::test]
+    async fn test_deliveries_keep_order() {
+        // The first change is retried before the second is sent.
+        let (url, stub) = stub(1).await;
+        let config = json!({"name": "all", "url": url});
+        let (_, _, deliveries) = deliver(config, 3, 2).await;
+        assert!(deliveries
+            .iter()
+            .all(|d| d.status == DeliveryStatus::Delivered));
+
+        let seqs = stub
+            .requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, body)| serde_json::from_str::<Value>(body).unwrap()["seq"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(seqs, [json!(1), json!(1), json!(2)]);
+    }
+
+    #[test]
+    fn test_sign() {
+        // From RFC 4231, test case 2.
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
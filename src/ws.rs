@@ -0,0 +1,336 @@
+use std::{collections::BTreeMap, net::Ipv4Addr, sync::Arc};
+
+use axum::{
+    extract::ws::{Message, WebSocket},
+    http::{header, HeaderMap},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    changes::{ChangeFilter, Cursor, FilterConfig},
+    db::DB,
+    model::MacAddr,
+    render::TimeFormat,
+    source::{SourceFilter, SourceNames},
+};
+
+/// How many subscriptions one connection can have at once.
+const MAX_SUBSCRIPTIONS: usize = 32;
+
+/// Origins other than this host's whose pages may open a WebSocket.
+#[derive(Debug, Clone, Default)]
+pub struct AllowedOrigins(pub Arc<[String]>);
+
+impl AllowedOrigins {
+    /// Whether a request with `headers` may be upgraded. Browsers always send
+    /// `Origin`, so without one the client isn't a page on another site.
+    pub fn allows(&self, headers: &HeaderMap) -> bool {
+        let Some(origin) = headers.get(header::ORIGIN) else {
+            return true;
+        };
+        let Ok(origin) = origin.to_str() else {
+            return false;
+        };
+        let origin = origin.trim_end_matches('/');
+        let same_host = origin
+            .split_once("://")
+            .zip(headers.get(header::HOST))
+            .is_some_and(|((_, host), expected)| host.as_bytes() == expected.as_bytes());
+
+        same_host
+            || self
+                .0
+                .iter()
+                .any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin))
+    }
+}
+
+/// A message from the client.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    /// Send the matching devices now and the matching changes from then on.
+    Subscribe {
+        id: String,
+        #[serde(default)]
+        filter: FilterConfig,
+    },
+    Unsubscribe {
+        id: String,
+    },
+    /// Devices by MAC or IP, like `/mac/:mac` and `/ip/:ip`.
+    Lookup {
+        id: Option<String>,
+        mac: Option<MacAddr>,
+        ip: Option<Ipv4Addr>,
+        source: Option<String>,
+    },
+}
+
+struct Subscription {
+    filter: ChangeFilter,
+    /// The last sequence number sent or covered by the snapshot.
+    cursor: Cursor,
+}
+
+/// One client's subscriptions.
+pub struct Session {
+    db: DB,
+    names: SourceNames,
+    format: TimeFormat,
+    subscriptions: BTreeMap<String, Subscription>,
+}
+
+impl Session {
+    pub fn new(db: DB, names: SourceNames, format: TimeFormat) -> Self {
+        Self {
+            db,
+            names,
+            format,
+            subscriptions: BTreeMap::new(),
+        }
+    }
+
+    /// The replies to a message from the client.
+    fn handle(&mut self, text: &str) -> Vec<Value> {
+        match serde_json::from_str(text) {
+            Ok(request) => self.request(request),
+            Err(e) => vec![error(None, &e)],
+        }
+    }
+
+    fn request(&mut self, request: Request) -> Vec<Value> {
+        match request {
+            Request::Subscribe { id, filter } => {
+                if self.subscriptions.len() >= MAX_SUBSCRIPTIONS
+                    && !self.subscriptions.contains_key(&id)
+                {
+                    return vec![error(Some(&id), &"too many subscriptions")];
+                }
+                let filter = match filter.build(&self.names) {
+                    Ok(filter) => filter,
+                    Err(e) => return vec![error(Some(&id), &e)],
+                };
+                let db = self.db.load();
+                let devices = db
+                    .devices()
+                    .into_iter()
+                    .filter(|device| filter.matches_device(device))
+                    .collect::<Vec<_>>();
+                let cursor = db.journal.cursor(db.journal.last_seq());
+                let snapshot = json!({
+                    "type": "snapshot",
+                    "id": id,
+                    "epoch": db.journal.epoch(),
+                    "last_seq": cursor.seq,
+                    "devices": self.format.apply(devices),
+                });
+                self.subscriptions
+                    .insert(id, Subscription { filter, cursor });
+                vec![snapshot]
+            }
+            Request::Unsubscribe { id } => {
+                if self.subscriptions.remove(&id).is_none() {
+                    return vec![error(Some(&id), &"not subscribed")];
+                }
+                vec![json!({ "type": "unsubscribed", "id": id })]
+            }
+            Request::Lookup {
+                id,
+                mac,
+                ip,
+                source,
+            } => {
+                let filter = match SourceFilter::new(source, &self.names) {
+                    Ok(filter) => filter,
+                    Err(e) => return vec![error(id.as_deref(), &e)],
+                };
+                let db = self.db.load();
+                let devices = match (mac, ip) {
+                    (Some(mac), None) => db.find_by_mac(&mac),
+                    (None, Some(ip)) => db.find_by_ip(ip),
+                    _ => return vec![error(id.as_deref(), &"expected one of mac or ip")],
+                };
+                vec![json!({
+                    "type": "lookup",
+                    "id": id,
+                    "devices": self.format.apply(filter.retain(devices)),
+                })]
+            }
+        }
+    }
+
+    /// A delta for each new change a subscription matches, with the devices
+    /// now known for its MAC.
+    fn deltas(&mut self) -> Vec<Value> {
+        let db = self.db.load();
+        let journal = &db.journal;
+        let mut messages = Vec::new();
+        let format = self.format;
+        for (id, subscription) in &mut self.subscriptions {
+            if journal.missed(&subscription.cursor) {
+                // The client has to subscribe again for a new snapshot.
+                messages.push(json!({
+                    "type": "missed",
+                    "id": id,
+                    "epoch": journal.epoch(),
+                    "last_seq": journal.last_seq(),
+                }));
+                subscription.cursor = journal.cursor(journal.last_seq());
+                continue;
+            }
+            for change in journal.since(&subscription.cursor) {
+                if !subscription.filter.matches(change, &db.vendor_mapping) {
+                    continue;
+                }
+                let devices = db.find_by_mac(&change.hardware_ethernet);
+                messages.push(json!({
+                    "type": "delta",
+                    "id": id,
+                    "change": format.apply(change.clone()),
+                    "devices": format.apply(subscription.filter.source.retain(devices)),
+                }));
+            }
+            subscription.cursor = journal.cursor(journal.last_seq());
+        }
+        messages
+    }
+}
+
+fn error(id: Option<&str>, error: &dyn std::fmt::Display) -> Value {
+    json!({
+        "type": "error",
+        "id": id,
+        "error": error.to_string(),
+    })
+}
+
+/// Answer the client's messages and send deltas after each reload until
+/// either side closes the socket.
+pub async fn serve(mut socket: WebSocket, mut session: Session, shutdown: CancellationToken) {
+    let mut journaled = session.db.subscribe();
+    loop {
+        let replies = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => session.handle(&text),
+                Some(Ok(Message::Binary(_))) => vec![error(None, &"expected a text message")],
+                Some(Ok(_)) => continue,
+                Some(Err(_)) | None => return,
+            },
+            changed = journaled.changed() => match changed {
+                Ok(()) => session.deltas(),
+                Err(_) => break,
+            },
+            () = shutdown.cancelled() => break,
+        };
+        for reply in replies {
+            if socket.send(Message::Text(reply.to_string())).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    // The client may already be gone.
+    let _ = socket.close().await;
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used, clippy::expect_used)]
+    use std::sync::Arc;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{
+        db::{self, Database},
+        model::Lease,
+    };
+
+    #[tokio::test]
+    async fn test_session() {
+        let db = DB::new(Database::in_memory(&["lan"]));
+        let names = SourceNames(Arc::from(["lan".to_owned()]));
+        let lease = |d, hostname| Lease::test(d).active_at(Utc::now()).with_hostname(hostname);
+        db::update_leases(&db, "lan", vec![lease(1, "one"), lease(2, "two")])
+            .await
+            .unwrap();
+        let mut session = Session::new(db.clone(), names, TimeFormat::default());
+
+        let replies = session.handle(
+            r#"{"type": "subscribe", "id": "a", "filter": {"macs": ["00:00:00:00:00:01"], "events": ["changed"]}}"#,
+        );
+        assert_eq!(replies[0]["type"], "snapshot");
+        assert_eq!(replies[0]["devices"].as_array().unwrap().len(), 1);
+        assert_eq!(replies[0]["devices"][0]["hostname"], "one");
+        session.handle(r#"{"type": "subscribe", "id": "b", "filter": {"cidr": "10.0.0.0/24"}}"#);
+
+        db::update_leases(&db, "lan", vec![lease(1, "uno"), lease(3, "three")])
+            .await
+            .unwrap();
+        let deltas = session.deltas();
+        let summary = deltas
+            .iter()
+            .map(|d| {
+                (
+                    d["id"].as_str().unwrap(),
+                    d["change"]["event"].as_str().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("a", "hostname_changed"),
+                ("b", "hostname_changed"),
+                ("b", "device_added"),
+                ("b", "device_left")
+            ]
+        );
+        assert_eq!(deltas[0]["devices"][0]["hostname"], "uno");
+        assert!(session.deltas().is_empty());
+
+        let reply = session.handle(r#"{"type": "unsubscribe", "id": "a"}"#);
+        assert_eq!(reply[0]["type"], "unsubscribed");
+        let reply = session.handle(r#"{"type": "unsubscribe", "id": "a"}"#);
+        assert_eq!(reply[0]["type"], "error");
+
+        let reply = session.handle(r#"{"type": "lookup", "id": "q", "ip": "10.0.0.3"}"#);
+        assert_eq!(reply[0]["devices"][0]["hostname"], "three");
+        let reply = session.handle(r#"{"type": "lookup", "ip": "10.0.0.3", "source": "lab"}"#);
+        assert_eq!(reply[0]["type"], "error");
+        assert_eq!(session.handle("nonsense")[0]["type"], "error");
+
+        for i in session.subscriptions.len()..MAX_SUBSCRIPTIONS {
+            let reply = session.handle(&format!(r#"{{"type": "subscribe", "id": "{i}"}}"#));
+            assert_eq!(reply[0]["type"], "snapshot");
+        }
+        let reply = session.handle(r#"{"type": "subscribe", "id": "one too many"}"#);
+        assert_eq!(reply[0]["type"], "error");
+        // Replacing a subscription is still allowed.
+        let reply = session.handle(r#"{"type": "subscribe", "id": "b"}"#);
+        assert_eq!(reply[0]["type"], "snapshot");
+    }
+
+    #[test]
+    fn test_allowed_origins() {
+        let allowed = AllowedOrigins(Arc::from(["https://dash.example.com/".to_owned()]));
+        let headers = |origin: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::HOST, "dhcp.lan:16768".parse().unwrap());
+            if let Some(origin) = origin {
+                headers.insert(header::ORIGIN, origin.parse().unwrap());
+            }
+            headers
+        };
+
+        assert!(allowed.allows(&headers(None)));
+        assert!(allowed.allows(&headers(Some("http://dhcp.lan:16768"))));
+        assert!(allowed.allows(&headers(Some("https://dash.example.com"))));
+        assert!(!allowed.allows(&headers(Some("https://evil.example.com"))));
+        assert!(!allowed.allows(&headers(Some("http://dhcp.lan"))));
+        assert!(!allowed.allows(&headers(Some("null"))));
+    }
+}